@@ -0,0 +1,241 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+	TILE_SIZE,
+	GameState,
+	level::{
+		Map,
+		ActiveRoom,
+		is_solid,
+		tile_at,
+	},
+	player::{
+		Player,
+		Health,
+	},
+};
+
+/*
+Enemy brains. Every enemy with a Brain gets moved between these states from what it can see:
+
+	Idle   <-> Patrol    nothing in sight, swap every so often
+	Patrol  -> Alert     spotted the player
+	Alert   -> Chase     kept them in sight for the senses' alert_time
+	Chase  <-> Attack    within attack range
+	any     -> Flee      hurt below flee_below and can still see the player
+	any     -> Patrol    lost sight of the player for LOSE_TIME
+
+"Sees" means the player is within sight range and a ray across the room grid doesn't cross a
+solid tile. What each state looks like is up to the enemy's own movement system, this only
+decides which one it's in. F3 shows every enemy's state above its head.
+*/
+
+const IDLE_TIME: f32 = 2.; //seconds idling before patrolling again
+const PATROL_TIME: f32 = 6.; //seconds patrolling before taking a break
+const LOSE_TIME: f32 = 2.; //seconds out of sight before giving up on the player
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiState {
+	Idle,
+	Patrol,
+	Alert,
+	Chase,
+	Attack,
+	Flee,
+}
+
+// How an enemy type notices the player, each enemy module has its own
+#[derive(Debug, Clone, Copy)]
+pub struct Senses {
+	pub sight: f32, //how far it can see
+	pub attack: f32, //how close it has to be to attack
+	pub flee_below: f32, //runs away under this fraction of its health, 0 never runs
+	pub alert_time: f32, //seconds between spotting the player and going after them
+}
+
+#[derive(Component)]
+pub struct Brain {
+	pub state: AiState,
+	senses: Senses,
+	max_health: f32,
+	in_state: f32, //seconds since the last state change
+	unseen: f32, //seconds since it last saw the player
+}
+
+impl Brain {
+	pub fn new(senses: Senses, max_health: f32) -> Self {
+		Self { state: AiState::Patrol, senses, max_health, in_state: 0., unseen: 0. }
+	}
+}
+
+// Which state comes next, given how far away the player is if they're in sight
+pub fn next_state(brain: &Brain, seen: Option<f32>, health: f32) -> AiState {
+	let senses = &brain.senses;
+	let hurt = senses.flee_below > 0. && health < brain.max_health * senses.flee_below;
+	match (brain.state, seen) {
+		(_, Some(_)) if hurt => AiState::Flee,
+		(AiState::Idle | AiState::Patrol, Some(_)) => AiState::Alert,
+		(AiState::Alert, Some(_)) if brain.in_state >= senses.alert_time => AiState::Chase,
+		(AiState::Chase | AiState::Attack, Some(distance)) if distance <= senses.attack => AiState::Attack,
+		(AiState::Attack, Some(_)) => AiState::Chase,
+		(AiState::Idle, None) if brain.in_state >= IDLE_TIME => AiState::Patrol,
+		(AiState::Patrol, None) if brain.in_state >= PATROL_TIME => AiState::Idle,
+		(AiState::Alert | AiState::Chase | AiState::Attack | AiState::Flee, None) if brain.unseen >= LOSE_TIME => AiState::Patrol,
+		(state, _) => state,
+	}
+}
+
+// Walks a ray across the room grid, false if it crosses a solid tile other than the one it starts in
+pub fn clear_line(room: &[[char; crate::ROOM_WIDTH]; crate::ROOM_HEIGHT], from: Vec3, to: Vec3) -> bool {
+	let start = tile_at(from);
+	let steps = ((to - from).truncate().length() / (TILE_SIZE / 4.)).ceil().max(1.) as usize;
+	(1..steps).all(|i| {
+		let point = from.lerp(to, i as f32 / steps as f32);
+		match tile_at(point) {
+			Some(tile) if Some(tile) != start => !is_solid(room[tile.1][tile.0]),
+			_ => true,
+		}
+	})
+}
+
+// F3 toggles the state labels
+#[derive(Default)]
+pub struct AiDebug(bool);
+
+#[derive(Component)]
+struct StateLabel(Entity);
+
+pub struct AiPlugin;
+impl Plugin for AiPlugin {
+	fn build (&self, app: &mut App) {
+		app.init_resource::<AiDebug>()
+			.add_system(think.run_in_state(GameState::Playing))
+			.add_system(toggle_debug.run_in_state(GameState::Playing))
+			.add_system(draw_debug.run_in_state(GameState::Playing));
+	}
+}
+
+fn think(
+	time: Res<Time>,
+	active_room: Option<Res<ActiveRoom>>,
+	map_query: Query<&Map>,
+	player_query: Query<&Transform, With<Player>>,
+	mut brain_query: Query<(&mut Brain, &Transform, &Health)>,
+) {
+	let (active_room, map) = match (active_room, map_query.get_single()) {
+		(Some(room), Ok(map)) => (room, map),
+		_ => return,
+	};
+	let room = match map.room(active_room.0, active_room.1) {
+		Some(room) => &room.room_coords,
+		None => return,
+	};
+	let player = player_query.get_single().map(|p| p.translation).ok();
+	let dt = time.delta_seconds();
+
+	for (mut brain, transform, health) in brain_query.iter_mut() {
+		let seen = player
+			.map(|p| (p, transform.translation.truncate().distance(p.truncate())))
+			.filter(|(p, distance)| *distance <= brain.senses.sight && clear_line(room, transform.translation, *p))
+			.map(|(_, distance)| distance);
+
+		brain.in_state += dt;
+		brain.unseen = if seen.is_some() { 0. } else { brain.unseen + dt };
+		let next = next_state(&brain, seen, health.health);
+		if next != brain.state {
+			brain.state = next;
+			brain.in_state = 0.;
+		}
+	}
+}
+
+fn toggle_debug(
+	input: Res<Input<KeyCode>>,
+	mut debug: ResMut<AiDebug>,
+) {
+	if input.just_pressed(KeyCode::F3) {
+		debug.0 = !debug.0;
+	}
+}
+
+// One label per brain, following it around and going away with it
+fn draw_debug(
+	mut commands: Commands,
+	debug: Res<AiDebug>,
+	asset_server: Res<AssetServer>,
+	brain_query: Query<(Entity, &Brain, &Transform), Without<StateLabel>>,
+	mut label_query: Query<(Entity, &StateLabel, &mut Text, &mut Transform)>,
+) {
+	let mut labelled = Vec::new();
+	for (label_entity, label, mut text, mut transform) in label_query.iter_mut() {
+		match brain_query.get(label.0) {
+			Ok((_, brain, brain_transform)) if debug.0 => {
+				text.sections[0].value = format!("{:?}", brain.state);
+				transform.translation = brain_transform.translation + Vec3::new(0., TILE_SIZE / 2., 100.);
+				labelled.push(label.0);
+			}
+			_ => commands.entity(label_entity).despawn(),
+		}
+	}
+	if !debug.0 {
+		return;
+	}
+	for (entity, brain, transform) in brain_query.iter() {
+		if labelled.contains(&entity) {
+			continue;
+		}
+		commands
+			.spawn_bundle(Text2dBundle {
+				text: Text::with_section(
+					format!("{:?}", brain.state),
+					TextStyle {
+						font: asset_server.load("quattrocentosans-bold.ttf"),
+						font_size: 20.,
+						color: Color::YELLOW,
+					},
+					TextAlignment {
+						vertical: VerticalAlign::Center,
+						horizontal: HorizontalAlign::Center,
+					},
+				),
+				transform: Transform::from_translation(transform.translation + Vec3::new(0., TILE_SIZE / 2., 100.)),
+				..default()
+			})
+			.insert(StateLabel(entity));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{ROOM_WIDTH, ROOM_HEIGHT, level::tile_center};
+
+	const SENSES: Senses = Senses { sight: 500., attack: 60., flee_below: 0.25, alert_time: 0.5 };
+
+	fn brain(state: AiState, in_state: f32) -> Brain {
+		Brain { state, senses: SENSES, max_health: 100., in_state, unseen: in_state }
+	}
+
+	#[test]
+	fn spotting_the_player_goes_through_alert_before_chasing() {
+		assert_eq!(next_state(&brain(AiState::Patrol, 0.), Some(200.), 100.), AiState::Alert);
+		assert_eq!(next_state(&brain(AiState::Alert, 0.1), Some(200.), 100.), AiState::Alert);
+		assert_eq!(next_state(&brain(AiState::Alert, 0.6), Some(200.), 100.), AiState::Chase);
+		assert_eq!(next_state(&brain(AiState::Chase, 0.), Some(40.), 100.), AiState::Attack);
+		assert_eq!(next_state(&brain(AiState::Attack, 0.), Some(40.), 10.), AiState::Flee);
+		assert_eq!(next_state(&brain(AiState::Chase, LOSE_TIME), None, 100.), AiState::Patrol);
+	}
+
+	#[test]
+	fn walls_block_sight() {
+		let mut room = [['-'; ROOM_WIDTH]; ROOM_HEIGHT];
+		let from = tile_center(2, 4).extend(0.);
+		let to = tile_center(10, 4).extend(0.);
+		assert!(clear_line(&room, from, to));
+		room[4][6] = '#';
+		assert!(!clear_line(&room, from, to));
+		//standing inside a brick doesn't block its own view
+		assert!(clear_line(&room, tile_center(6, 4).extend(0.), to));
+	}
+}
@@ -0,0 +1,141 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use bevy::sprite::collide_aabb::collide;
+
+use crate::{
+	TILE_SIZE,
+	GameState,
+	player::Player,
+	combat::HitEvent,
+};
+
+// The map is split into bands by depth, every room in a band shares its look and rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Biome {
+	Dirt,
+	Stone,
+	Crystal,
+	Magma,
+}
+
+pub struct BiomeInfo {
+	pub name: &'static str,
+	pub tile_frames: &'static [usize], //frames of tiles.png the bricks are drawn with
+	pub tile_tint: Color,
+	pub background_tint: Color,
+	pub brick_hardness: f32, //multiplier on brick health
+	pub enemy_mix: &'static [(char, u32)], //room tile for each enemy and how likely it is
+	pub hazards: &'static [(char, f32)], //room tile and 1 in n odds per open floor tile
+}
+
+// first room depth (map y) of each band after dirt
+const STONE_DEPTH: i32 = 3;
+const CRYSTAL_DEPTH: i32 = 8;
+const MAGMA_DEPTH: i32 = 14;
+
+const DIRT: BiomeInfo = BiomeInfo {
+	name: "dirt",
+	tile_frames: &[0, 1],
+	tile_tint: Color::rgb(0.75, 0.55, 0.35),
+	background_tint: Color::rgb(0.85, 0.7, 0.55),
+	brick_hardness: 1.0,
+	enemy_mix: &[('E', 3), ('G', 1)],
+	hazards: &[('W', 30.)],
+};
+
+const STONE: BiomeInfo = BiomeInfo {
+	name: "stone",
+	tile_frames: &[1, 2],
+	tile_tint: Color::rgb(0.8, 0.8, 0.85),
+	background_tint: Color::rgb(0.6, 0.6, 0.65),
+	brick_hardness: 1.5,
+	enemy_mix: &[('E', 1), ('G', 1)],
+	hazards: &[('W', 20.)],
+};
+
+const CRYSTAL: BiomeInfo = BiomeInfo {
+	name: "crystal",
+	tile_frames: &[2, 3],
+	tile_tint: Color::rgb(0.55, 0.75, 1.0),
+	background_tint: Color::rgb(0.45, 0.5, 0.75),
+	brick_hardness: 2.0,
+	enemy_mix: &[('E', 2), ('G', 1)],
+	hazards: &[('W', 25.)],
+};
+
+const MAGMA: BiomeInfo = BiomeInfo {
+	name: "magma",
+	tile_frames: &[3, 0],
+	tile_tint: Color::rgb(0.9, 0.45, 0.3),
+	background_tint: Color::rgb(0.7, 0.3, 0.2),
+	brick_hardness: 2.5,
+	enemy_mix: &[('E', 1)],
+	hazards: &[('L', 12.)],
+};
+
+const LAVA_DAMAGE: f32 = 10.;
+pub const LAVA_TINT: Color = Color::rgb(1.0, 0.35, 0.05);
+
+impl Biome {
+	pub fn at_depth(y: i32) -> Self {
+		if y >= MAGMA_DEPTH {
+			Biome::Magma
+		} else if y >= CRYSTAL_DEPTH {
+			Biome::Crystal
+		} else if y >= STONE_DEPTH {
+			Biome::Stone
+		} else {
+			Biome::Dirt
+		}
+	}
+
+	pub fn info(&self) -> &'static BiomeInfo {
+		match self {
+			Biome::Dirt => &DIRT,
+			Biome::Stone => &STONE,
+			Biome::Crystal => &CRYSTAL,
+			Biome::Magma => &MAGMA,
+		}
+	}
+}
+
+impl BiomeInfo {
+	// picks which enemy tile to place from the band's mix
+	pub fn pick_enemy(&self, rng: &mut impl rand::Rng) -> char {
+		let total: u32 = self.enemy_mix.iter().map(|(_, weight)| weight).sum();
+		let mut roll = rng.gen_range(0..total.max(1));
+		for (tile, weight) in self.enemy_mix {
+			if roll < *weight {
+				return *tile;
+			}
+			roll -= weight;
+		}
+		'E'
+	}
+}
+
+#[derive(Component)]
+pub struct Lava;
+
+pub struct BiomePlugin;
+impl Plugin for BiomePlugin {
+	fn build (&self, app: &mut App) {
+		app.add_system(lava_damage.run_in_state(GameState::Playing));
+	}
+}
+
+// Burns whenever the player's invincibility runs out, apply_hits keeps it to one burn per window
+fn lava_damage(
+	lava_query: Query<&Transform, (With<Lava>, Without<Player>)>,
+	player_query: Query<(Entity, &Transform), With<Player>>,
+	mut hits: EventWriter<HitEvent>,
+) {
+	for (player_entity, player_transform) in player_query.iter() {
+		let burning = lava_query.iter().any(|lava| {
+			collide(player_transform.translation, Vec2::splat(50.), lava.translation, Vec2::splat(TILE_SIZE)).is_some()
+		});
+		if burning {
+			hits.send(HitEvent { target: player_entity, damage: LAVA_DAMAGE, knockback: Vec2::ZERO });
+		}
+	}
+}
@@ -5,29 +5,129 @@ use bevy::sprite::collide_aabb::collide;
 
 
 
+use rand::{
+	rngs::StdRng,
+	seq::SliceRandom,
+	SeedableRng,
+};
+
 use crate::{
 	GameState,
 	TILE_SIZE,
-	level::Collider,
+	level::{
+		Collider,
+		Brick,
+		Map,
+		ActiveRoom,
+		RoomTile,
+		POUND_SALT,
+		mix_seed,
+	},
 	loading::{
 		LoadingAssets,
 		LoadingAssetInfo,
 	},
     player::{
-		Player
+		Player,
+		Bomb,
+		Health,
+	},
+	nav::NavAgent,
+	boss_fight::Defeated,
+	combat::{
+		HitEvent,
+		Armour,
+		hit_stop_over,
 	},
-	FRAME_TIME
 };
 
+const BOSS_SPEED: f32 = 2. * TILE_SIZE; //per second
+const BOSS_JUMP: f32 = 12. * TILE_SIZE; //launch speed, enough for a two tile ledge
+pub const BOSS_CONTACT_DAMAGE: f32 = 20.;
+pub const BOSS_KNOCKBACK: f32 = 14. * TILE_SIZE; //it's big, so it shoves harder than anything else
+pub const BOSS_HEALTH: f32 = 400.;
+pub const BOSS_SIZE: (f32, f32) = (260., 100.); //hitbox, smaller than the sprite
+const ENRAGE_SPEED: f32 = 1.5; //speed multiplier once it's under half health
+const BOMB_RANGE: f32 = 3. * TILE_SIZE; //hides in its shell when a bomb is this close
+const SHELL_TIME: f32 = 1.2; //seconds it stays hidden after the last bomb nearby is gone
+const SHELL_ARMOUR: f32 = 0.2; //fraction of damage that gets through the shell
+const SPIN_SPEED: f32 = 9. * TILE_SIZE;
+const SPIN_TIME: f32 = 2.5;
+const POUND_COOLDOWN: f32 = 6.;
+const POUND_RANGE: f32 = 4. * TILE_SIZE; //only pounds when the player is within this many pixels sideways
+const POUND_JUMP: f32 = 16. * TILE_SIZE;
+const POUND_BRICKS: usize = 3; //bricks shaken loose per pound, doubled when enraged
+const ROCK_DAMAGE: f32 = 15.;
+const ROCK_KNOCKBACK: f32 = 4. * TILE_SIZE;
+
+// What the turtle is doing. It chases by default, ducks into its shell when a bomb lands nearby,
+// comes out of the shell spinning at the player, and every so often leaps up and pounds the floor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BossPhase {
+	Chase,
+	Shell,
+	Spin,
+	Pound,
+}
+
 #[derive(Component)]
 pub struct Boss{
-	pub health: f32,
+	pub phase: BossPhase,
+	phase_timer: Timer,
+	pound_cooldown: Timer,
 	pub y_velocity: f32,
-	pub x_velocity: f32,
-	pub y_accel: f32,
-	pub last_move: f32,
+	pub x_velocity: f32, //px per second
 	pub turtled: bool,
-	pub path: Vec3
+	pub grounded: bool,
+	pounds: u64, //ground pounds so far this fight
+	max_health: f32, //what it spawned with, scaled by the room's difficulty
+}
+
+impl Boss {
+	pub fn new(max_health: f32) -> Self {
+		Self {
+			phase: BossPhase::Chase,
+			phase_timer: Timer::from_seconds(0., false),
+			pound_cooldown: Timer::from_seconds(POUND_COOLDOWN, false),
+			y_velocity: 0.,
+			x_velocity: 0.,
+			turtled: false,
+			grounded: false,
+			pounds: 0,
+			max_health,
+		}
+	}
+
+	// Below half its health it moves faster, looks angrier and pounds harder
+	pub fn enraged(&self, health: &Health) -> bool {
+		health.health <= self.max_health / 2.
+	}
+
+	fn enter(&mut self, phase: BossPhase, seconds: f32) {
+		self.phase = phase;
+		self.phase_timer = Timer::from_seconds(seconds, false);
+		self.turtled = matches!(phase, BossPhase::Shell | BossPhase::Spin);
+	}
+}
+
+// Where a phase goes next, checked once the current one has had its say this frame
+fn next_phase(phase: BossPhase, phase_done: bool, bomb_near: bool, pound_ready: bool) -> Option<BossPhase> {
+	match phase {
+		BossPhase::Chase if bomb_near => Some(BossPhase::Shell),
+		BossPhase::Chase if pound_ready => Some(BossPhase::Pound),
+		BossPhase::Shell if phase_done && !bomb_near => Some(BossPhase::Spin),
+		BossPhase::Spin if phase_done => Some(BossPhase::Chase),
+		_ => None,
+	}
+}
+
+// Sent when the turtle lands from a ground pound
+pub struct GroundPound;
+
+// Brick knocked out of the ceiling by a ground pound, falls until it hits something
+#[derive(Component)]
+pub struct FallingBrick {
+	y_velocity: f32,
 }
 
 #[derive(Deref, DerefMut)]
@@ -36,9 +136,12 @@ pub struct BossSheet(Handle<TextureAtlas>);
 pub struct BossPlugin;
 impl Plugin for BossPlugin {
 	fn build (&self, app: &mut App) {
-		app.add_enter_system(GameState::Loading, load_boss_sheet)
-		.add_system(boss_movement_system)
-		.add_system(boss_animate);
+		app.add_event::<GroundPound>()
+		.add_enter_system(GameState::Loading, load_boss_sheet)
+		.add_system(boss_movement_system.run_in_state(GameState::Playing).run_if(hit_stop_over))
+		.add_system(boss_animate)
+		.add_system(shake_loose_bricks.run_in_state(GameState::Playing))
+		.add_system(falling_bricks.run_in_state(GameState::Playing).run_if(hit_stop_over));
 		
 	}
 }
@@ -65,78 +168,184 @@ fn load_boss_sheet(
 
 fn boss_movement_system(
 	time: Res<Time>, 
-	mut query: Query<(&mut Transform,&mut Boss), (With<Boss>,Without<Player>)>,
-	mut player: Query<&mut Transform, With<Player>>,
+	mut query: Query<(&mut Transform, &mut Boss, &mut NavAgent, &mut Armour, &Health), (Without<Defeated>, Without<Player>)>,
+	player: Query<&Transform, (With<Player>, Without<Boss>)>,
+	bombs: Query<&Transform, (With<Bomb>, Without<Boss>, Without<Player>)>,
 	collision: Query<&Transform, (With<Collider>, Without<Player>,Without<Boss>)>,
+	mut pounds: EventWriter<GroundPound>,
 ){
-	let now = time.seconds_since_startup() as f32;
-	for player_transform in player.iter_mut() {
-		for (mut transform,mut boss) in query.iter_mut(){
-			//current position
-			
-			let (x_org, y_org) = (transform.translation.x, transform.translation.y);
-			let (px_org,py_org) = (player_transform.translation.x, player_transform.translation.y);
-			let path: Vec3 = Vec3::new(x_org- px_org,y_org-py_org,900.0);
-			
-			if  boss.last_move < (now + FRAME_TIME){ //if player in LOS run towards player
-				boss.last_move = now;
-				let deltax = {
-					if boss.path.x>0.0 {
-						-10.0 * FRAME_TIME
-					}else{
-						10.0 * FRAME_TIME
+	let dt = time.delta_seconds();
+	for player_transform in player.iter() {
+		for (mut transform, mut boss, mut agent, mut armour, health) in query.iter_mut(){
+			let speed = if boss.enraged(health) { BOSS_SPEED * ENRAGE_SPEED } else { BOSS_SPEED };
+			boss.phase_timer.tick(time.delta());
+			boss.pound_cooldown.tick(time.delta());
+
+			let bomb_near = bombs.iter().any(|bomb| bomb.translation.distance(transform.translation) < BOMB_RANGE);
+			let to_player = player_transform.translation - transform.translation;
+			let pound_ready = boss.grounded && boss.pound_cooldown.finished() && to_player.x.abs() < POUND_RANGE;
+			match next_phase(boss.phase, boss.phase_timer.finished(), bomb_near, pound_ready) {
+				Some(BossPhase::Shell) => boss.enter(BossPhase::Shell, SHELL_TIME),
+				Some(BossPhase::Spin) => {
+					boss.enter(BossPhase::Spin, SPIN_TIME);
+					boss.x_velocity = to_player.x.signum() * SPIN_SPEED;
+				}
+				Some(BossPhase::Pound) => {
+					boss.enter(BossPhase::Pound, 0.);
+					boss.pound_cooldown.reset();
+					boss.y_velocity = POUND_JUMP;
+				}
+				Some(BossPhase::Chase) => boss.enter(BossPhase::Chase, 0.),
+				None => {}
+			}
+			//keep hiding for a bit after every bomb
+			if boss.phase == BossPhase::Shell && bomb_near {
+				boss.phase_timer.reset();
+			}
+			armour.0 = if boss.turtled { SHELL_ARMOUR } else { 1. };
+			agent.active = boss.phase == BossPhase::Chase;
+
+			let x_velocity = match boss.phase {
+				BossPhase::Shell => 0.,
+				BossPhase::Spin => boss.x_velocity,
+				//drift over the player while in the air
+				BossPhase::Pound => if to_player.x.abs() < 4. { 0. } else { to_player.x.signum() * speed },
+				BossPhase::Chase => {
+					//follow the nav path, or head straight for the player if there isn't one
+					let waypoint = agent.next_waypoint(transform.translation)
+						.unwrap_or_else(|| player_transform.translation.truncate());
+					let to_waypoint = waypoint - transform.translation.truncate();
+
+					//jump links go up, so jump when the next tile is above
+					if boss.grounded && to_waypoint.y > TILE_SIZE / 2. {
+						boss.y_velocity = BOSS_JUMP;
+					}
+					if to_waypoint.x.abs() < 4. {
+						0.
+					} else {
+						to_waypoint.x.signum() * speed
 					}
-				};
-				
-				let target = transform.translation + Vec3::new(deltax, 0., 0.);
-				if check_tile_collision(target, &collision){
-					transform.translation = target;
-					boss.x_velocity = deltax;
 				}
+			};
 			
-				
-				boss.y_velocity += -25.0 * TILE_SIZE * FRAME_TIME;
-
-				let deltay = boss.y_velocity * FRAME_TIME;
-				let target = transform.translation + Vec3::new(0., deltay, 0.);
-				if check_tile_collision(target, &collision){
-					transform.translation = target;
-					
-				}else{
-					boss.y_velocity = 0.0;
+			let target = transform.translation + Vec3::new(x_velocity * dt, 0., 0.);
+			if check_tile_collision(target, &collision){
+				transform.translation = target;
+				if boss.phase != BossPhase::Spin {
+					boss.x_velocity = x_velocity;
 				}
-				if (path.y > transform.translation.y || boss.path == Vec3::new(0.,0.,0.)) && check_tile_collision(path, &collision){
-					boss.path = path;
-				}
-				
+			} else if boss.phase == BossPhase::Spin {
+				//bounce off walls while spinning
+				boss.x_velocity = -boss.x_velocity;
+			}
+			
+			boss.y_velocity += -25.0 * TILE_SIZE * dt;
+
+			let was_grounded = boss.grounded;
+			let deltay = boss.y_velocity * dt;
+			let target = transform.translation + Vec3::new(0., deltay, 0.);
+			if check_tile_collision(target, &collision){
+				transform.translation = target;
+				boss.grounded = false;
+			}else{
+				boss.grounded = boss.y_velocity < 0.;
+				boss.y_velocity = 0.0;
+			}
+
+			if boss.phase == BossPhase::Pound && boss.grounded && !was_grounded {
+				boss.pounds += 1;
+				pounds.send(GroundPound);
+				boss.enter(BossPhase::Chase, 0.);
 			}
 		}
 	}
 }
 
 fn boss_animate(
-    _texture_atlases: Res<Assets<TextureAtlas>>,
-	mut boss: Query<
-		(
-			&mut Boss,
-			&mut TextureAtlasSprite,
-			&Handle<TextureAtlas>,
-			&mut Transform
-		),
-		With<Boss>
-	>,
+	time: Res<Time>,
+	mut boss: Query<(&Boss, &Health, &mut TextureAtlasSprite, &mut Transform), Without<Defeated>>,
 ){
-    for (boss,mut sprite, _texture_atlas_handle, mut transform) in boss.iter_mut() {
-        if boss.health <= 50.0{
-           sprite.index = 1;
-        }
-		//if bomb near (turtled = true; index =2)
-		if  boss.x_velocity < 0.0 {
+	for (boss, health, mut sprite, mut transform) in boss.iter_mut() {
+		sprite.index = if boss.turtled {
+			2
+		} else if boss.enraged(health) {
+			1
+		} else {
+			0
+		};
+		if boss.phase == BossPhase::Spin {
+			let turns = time.seconds_since_startup() as f32 * 4. * std::f32::consts::TAU;
+			transform.rotation = Quat::from_rotation_z(-boss.x_velocity.signum() * turns);
+		} else if boss.x_velocity < 0.0 {
 			transform.rotation = Quat::from_rotation_y(std::f32::consts::PI);
 		} else {
 			transform.rotation = Quat::default();
 		}
-    }
+	}
+}
+
+// Each pound knocks a few bricks with open space under them out of the room, which ones comes from
+// the map seed and how many pounds there have been this fight
+fn shake_loose_bricks(
+	mut commands: Commands,
+	mut pounds: EventReader<GroundPound>,
+	active_room: Option<Res<ActiveRoom>>,
+	map_query: Query<&Map>,
+	boss_query: Query<(&Boss, &Health)>,
+	brick_query: Query<(Entity, &RoomTile), (With<Brick>, With<Collider>)>,
+) {
+	if pounds.iter().count() == 0 {
+		return;
+	}
+	let (active_room, map) = match (active_room, map_query.get_single()) {
+		(Some(room), Ok(map)) => (room, map),
+		_ => return,
+	};
+	let room = match map.room(active_room.0, active_room.1) {
+		Some(room) => &room.room_coords,
+		None => return,
+	};
+	let enraged = boss_query.iter().any(|(boss, health)| boss.enraged(health));
+	let pound_count = boss_query.iter().map(|(boss, _)| boss.pounds).max().unwrap_or(0);
+	let count = if enraged { POUND_BRICKS * 2 } else { POUND_BRICKS };
+
+	let mut loose: Vec<Entity> = brick_query.iter()
+		.filter(|(_, tile)| room[tile.y][tile.x] == '#' && tile.y + 1 < room.len() && room[tile.y + 1][tile.x] == '-')
+		.map(|(entity, _)| entity)
+		.collect();
+	let mut rng = StdRng::seed_from_u64(mix_seed(map.seed(), active_room.0, active_room.1, POUND_SALT) ^ pound_count);
+	loose.shuffle(&mut rng);
+	for entity in loose.into_iter().take(count) {
+		commands.entity(entity)
+			.remove::<Collider>()
+			.remove::<Brick>()
+			.remove::<Health>()
+			.insert(FallingBrick { y_velocity: 0. });
+	}
+}
+
+fn falling_bricks(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut hits: EventWriter<HitEvent>,
+	mut brick_query: Query<(Entity, &mut FallingBrick, &mut Transform)>,
+	player_query: Query<(Entity, &Transform), (With<Player>, Without<FallingBrick>)>,
+	collision: Query<&Transform, (With<Collider>, Without<FallingBrick>)>,
+) {
+	let dt = time.delta_seconds();
+	for (entity, mut brick, mut transform) in brick_query.iter_mut() {
+		brick.y_velocity += -25.0 * TILE_SIZE * dt;
+		transform.translation.y += brick.y_velocity * dt;
+		let pos = transform.translation;
+
+		if let Some((player_entity, player_transform)) = player_query.iter()
+			.find(|(_, player)| collide(pos, Vec2::splat(TILE_SIZE * 0.8), player.translation, Vec2::splat(50.)).is_some()) {
+			hits.send(HitEvent::from_point(player_entity, ROCK_DAMAGE, ROCK_KNOCKBACK, pos, player_transform.translation));
+			commands.entity(entity).despawn();
+		} else if collision.iter().any(|wall| collide(pos, Vec2::splat(TILE_SIZE * 0.8), wall.translation, Vec2::splat(TILE_SIZE)).is_some()) {
+			commands.entity(entity).despawn();
+		}
+	}
 }
 
 fn check_tile_collision(
@@ -155,4 +364,32 @@ fn check_tile_collision(
 		}
 	}	
 	true
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn phases_cycle_through_the_shell() {
+		use BossPhase::*;
+		assert_eq!(next_phase(Chase, true, true, true), Some(Shell));
+		assert_eq!(next_phase(Chase, true, false, true), Some(Pound));
+		assert_eq!(next_phase(Chase, true, false, false), None);
+		//stays hidden while a bomb is still close
+		assert_eq!(next_phase(Shell, true, true, false), None);
+		assert_eq!(next_phase(Shell, false, false, false), None);
+		assert_eq!(next_phase(Shell, true, false, false), Some(Spin));
+		assert_eq!(next_phase(Spin, false, true, false), None);
+		assert_eq!(next_phase(Spin, true, false, false), Some(Chase));
+		//pounds only finish by landing
+		assert_eq!(next_phase(Pound, true, true, true), None);
+	}
+
+	#[test]
+	fn enrages_at_half_of_its_own_health() {
+		//a deep lair boss has more than the base health
+		let boss = Boss::new(BOSS_HEALTH * 2.);
+		assert!(!boss.enraged(&Health::from(BOSS_HEALTH * 1.2)));
+		assert!(boss.enraged(&Health::from(BOSS_HEALTH)));
+	}
+}
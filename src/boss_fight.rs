@@ -0,0 +1,246 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+	WIN_H,
+	TILE_SIZE,
+	ROOM_WIDTH,
+	ROOM_HEIGHT,
+	GameState,
+	level::{
+		Map,
+		ActiveRoom,
+		Brick,
+		BrickSheet,
+		Collider,
+		Unbreakable,
+		BombItemSheet,
+		HealthItemSheet,
+		is_solid,
+		spawn_pickup,
+		tile_at,
+		tile_center,
+	},
+	player::{
+		Player,
+		Health,
+	},
+	boss::Boss,
+	nav::{
+		NavAgent,
+		NavBlocker,
+	},
+};
+
+const BAR_LENGTH: f32 = 480.;
+const BAR_HEIGHT: f32 = 16.;
+const BAR_FRAME: f32 = 6.;
+const BAR_COLOR: Color = Color::rgb(0.75, 0.1, 0.1);
+const DEFEAT_TIME: f32 = 2.5; //seconds the turtle flails on its back before it's gone
+const VICTORY_TIME: f32 = 4.; //seconds to grab the loot before the credits roll
+const BOSS_LOOT: [(char, usize); 2] = [('H', 3), ('B', 2)];
+const SEAL_TINT: Color = Color::rgb(0.45, 0.2, 0.2);
+
+// Health bar along the top of the screen, for the boss in the current room
+#[derive(Component)]
+struct BossBar {
+	max: f32,
+}
+
+#[derive(Component)]
+struct BossBarFrame;
+
+// Boss has run out of health and is playing out its death
+#[derive(Component)]
+pub struct Defeated(Timer);
+
+// Block over one of the boss room's exits, there until the boss is beaten
+#[derive(Component)]
+struct LairSeal;
+
+// Counts down to the credits once the boss is gone
+struct Victory(Timer);
+
+pub struct BossFightPlugin;
+impl Plugin for BossFightPlugin {
+	fn build (&self, app: &mut App) {
+		app.add_system_set(
+				ConditionSet::new()
+					.run_in_state(GameState::Playing)
+					.with_system(show_boss_bar)
+					.with_system(update_boss_bar)
+					.with_system(defeat_boss)
+					.with_system(victory)
+					.with_system(seal_lair)
+					.into()
+			);
+	}
+}
+
+fn show_boss_bar(
+	mut commands: Commands,
+	boss_query: Query<&Health, Added<Boss>>,
+) {
+	for health in boss_query.iter() {
+		let y = WIN_H / 2. - TILE_SIZE * 0.3;
+		commands
+			.spawn_bundle(SpriteBundle {
+				sprite: Sprite {
+					color: Color::BLACK,
+					custom_size: Some(Vec2::new(BAR_LENGTH + BAR_FRAME, BAR_HEIGHT + BAR_FRAME)),
+					..default()
+				},
+				transform: Transform::from_xyz(0., y, 950.),
+				..default()
+			})
+			.insert(BossBarFrame);
+		commands
+			.spawn_bundle(SpriteBundle {
+				sprite: Sprite {
+					color: BAR_COLOR,
+					custom_size: Some(Vec2::new(BAR_LENGTH, BAR_HEIGHT)),
+					..default()
+				},
+				transform: Transform::from_xyz(0., y, 951.),
+				..default()
+			})
+			.insert(BossBar { max: health.health });
+	}
+}
+
+// Shrinks the bar towards its left end as the boss loses health
+fn update_boss_bar(
+	mut commands: Commands,
+	boss_query: Query<&Health, With<Boss>>,
+	mut bar_query: Query<(&BossBar, &mut Sprite, &mut Transform)>,
+	hud_query: Query<Entity, Or<(With<BossBar>, With<BossBarFrame>)>>,
+) {
+	let health = match boss_query.get_single() {
+		Ok(health) => health.health,
+		Err(_) => {
+			for entity in hud_query.iter() {
+				commands.entity(entity).despawn();
+			}
+			return;
+		}
+	};
+	for (bar, mut sprite, mut transform) in bar_query.iter_mut() {
+		let length = BAR_LENGTH * (health / bar.max).clamp(0., 1.);
+		sprite.custom_size = Some(Vec2::new(length, BAR_HEIGHT));
+		transform.translation.x = (length - BAR_LENGTH) / 2.;
+	}
+}
+
+// Flips the turtle on its back and blinks it out, then drops its loot and starts the win countdown
+fn defeat_boss(
+	mut commands: Commands,
+	time: Res<Time>,
+	bomb_sheet: Res<BombItemSheet>,
+	hp_sheet: Res<HealthItemSheet>,
+	fallen_query: Query<(Entity, &Health), (With<Boss>, Without<Defeated>)>,
+	mut defeated_query: Query<(Entity, &mut Defeated, &mut TextureAtlasSprite, &mut Transform)>,
+) {
+	for (entity, health) in fallen_query.iter() {
+		if health.health <= 0. {
+			info!("boss defeated");
+			commands.entity(entity)
+				.insert(Defeated(Timer::from_seconds(DEFEAT_TIME, false)))
+				.remove::<NavAgent>();
+		}
+	}
+
+	for (entity, mut defeated, mut sprite, mut transform) in defeated_query.iter_mut() {
+		defeated.0.tick(time.delta());
+		transform.rotation = Quat::from_rotation_z(std::f32::consts::PI);
+		sprite.color.set_a(if ((defeated.0.elapsed_secs() * 8.) as u32).is_multiple_of(2) { 1. } else { 0.3 });
+		if !defeated.0.finished() {
+			continue;
+		}
+
+		let ground = transform.translation - Vec3::new(0., 37., 0.);
+		let drops = BOSS_LOOT.iter().flat_map(|(item, count)| std::iter::repeat_n(*item, *count));
+		for (i, item) in drops.enumerate() {
+			let offset = (i as f32 - 2.) * TILE_SIZE * 0.6;
+			spawn_pickup(&mut commands, item, ground + Vec3::new(offset, 0., 0.), &bomb_sheet, &hp_sheet);
+		}
+		commands.entity(entity).despawn();
+		commands.insert_resource(Victory(Timer::from_seconds(VICTORY_TIME, false)));
+	}
+}
+
+// Shuts every way out of the lair once the player is properly inside with the boss alive, and opens
+// them back up once it's gone. Leaving while it plays out its defeat would keep its tile, and it
+// would be back at full health next time
+fn seal_lair(
+	mut commands: Commands,
+	brick_sheet: Res<BrickSheet>,
+	active_room: Option<Res<ActiveRoom>>,
+	map_query: Query<&Map>,
+	boss_query: Query<(), With<Boss>>,
+	player_query: Query<&Transform, With<Player>>,
+	seal_query: Query<Entity, With<LairSeal>>,
+) {
+	let (active_room, map) = match (active_room, map_query.get_single()) {
+		(Some(room), Ok(map)) => (room, map),
+		_ => return,
+	};
+	if boss_query.is_empty() || (active_room.0, active_room.1) != map.lair() {
+		for entity in seal_query.iter() {
+			commands.entity(entity).despawn();
+		}
+		return;
+	}
+	if !seal_query.is_empty() {
+		return;
+	}
+	//wait until they're clear of the doorway, or they'd be sealed into the wall
+	let inside = player_query.get_single().ok()
+		.and_then(|player| tile_at(player.translation))
+		.is_some_and(|(x, y)| x > 1 && y > 1 && x < ROOM_WIDTH - 2 && y < ROOM_HEIGHT - 1);
+	if !inside {
+		return;
+	}
+	let room = match map.room(active_room.0, active_room.1) {
+		Some(room) => &room.room_coords,
+		None => return,
+	};
+
+	info!("boss room sealed");
+	for (y, row) in room.iter().enumerate() {
+		for (x, tile) in row.iter().enumerate() {
+			let border = x == 0 || y == 0 || x == ROOM_WIDTH - 1 || y == ROOM_HEIGHT - 1;
+			if !border || is_solid(*tile) {
+				continue;
+			}
+			commands
+				.spawn_bundle(SpriteSheetBundle {
+					texture_atlas: brick_sheet.0.clone(),
+					sprite: TextureAtlasSprite {
+						index: 3,
+						color: SEAL_TINT,
+						..default()
+					},
+					transform: Transform::from_translation(tile_center(x, y).extend(100.)),
+					..default()
+				})
+				.insert(Brick)
+				.insert(Collider)
+				.insert(Unbreakable)
+				.insert(NavBlocker)
+				.insert(LairSeal);
+		}
+	}
+}
+
+fn victory(
+	mut commands: Commands,
+	time: Res<Time>,
+	victory: Option<ResMut<Victory>>,
+) {
+	if let Some(mut victory) = victory {
+		if victory.0.tick(time.delta()).just_finished() {
+			commands.remove_resource::<Victory>();
+			commands.insert_resource(NextState(GameState::Credits));
+		}
+	}
+}
@@ -0,0 +1,177 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use bevy::sprite::collide_aabb::collide;
+
+use crate::{
+	TILE_SIZE,
+	GameState,
+	level::{
+		Brick,
+		Collider,
+	},
+	player::{
+		Player,
+		Health,
+		InvincibilityTimer,
+	},
+};
+
+const HIT_STOP: f32 = 0.06; //seconds everything freezes when a hit lands
+const KNOCKBACK_DRAG: f32 = 10.; //how quickly a knockback dies off, per second
+const KNOCKBACK_SIZE: f32 = 50.; //collision box used while being knocked about
+
+// One attack landing on something with Health. Whoever deals damage sends one of these instead of
+// touching Health themselves, so every hit gets the same knockback, hit-stop and death handling.
+pub struct HitEvent {
+	pub target: Entity,
+	pub damage: f32,
+	pub knockback: Vec2, //starting velocity of the shove, px per second
+}
+
+impl HitEvent {
+	// A hit that shoves the target straight away from where it came from
+	pub fn from_point(target: Entity, damage: f32, knockback: f32, from: Vec3, to: Vec3) -> Self {
+		let away = (to - from).truncate().normalize_or_zero();
+		Self::towards(target, damage, knockback, Vec2::new(if away.x < 0. { -1. } else { 1. }, 0.))
+	}
+
+	// A hit that shoves the target along `dir`
+	pub fn towards(target: Entity, damage: f32, knockback: f32, dir: Vec2) -> Self {
+		//sideways hits always go a little upwards, so things hop back rather than skid
+		let dir = if dir.y == 0. { Vec2::new(dir.x, 0.5) } else { dir };
+		Self { target, damage, knockback: dir.normalize_or_zero() * knockback }
+	}
+}
+
+// Sent when a hit finishes something off, the frame it's despawned
+pub struct DeathEvent {
+	pub target: Entity,
+}
+
+#[derive(Component)]
+pub struct Knockback(Vec2);
+
+// Stays around at zero health so its own systems can play out the death
+#[derive(Component)]
+pub struct ScriptedDeath;
+
+// Fraction of each hit's damage that actually gets through, for things that can block
+#[derive(Component)]
+pub struct Armour(pub f32);
+
+// Counts down while the game is frozen for a hit
+pub struct HitStop(Timer);
+
+impl Default for HitStop {
+	fn default() -> Self {
+		let mut timer = Timer::from_seconds(HIT_STOP, false);
+		timer.tick(std::time::Duration::from_secs_f32(HIT_STOP));
+		Self(timer)
+	}
+}
+
+// Run condition for anything that moves, so it all holds still during hit-stop
+pub fn hit_stop_over(hit_stop: Res<HitStop>) -> bool {
+	hit_stop.0.finished()
+}
+
+pub struct CombatPlugin;
+impl Plugin for CombatPlugin {
+	fn build (&self, app: &mut App) {
+		app.add_event::<HitEvent>()
+			.add_event::<DeathEvent>()
+			.init_resource::<HitStop>()
+			.add_system(apply_hits.run_in_state(GameState::Playing))
+			.add_system(tick_hit_stop.run_in_state(GameState::Playing))
+			.add_system(apply_knockback.run_in_state(GameState::Playing).run_if(hit_stop_over));
+	}
+}
+
+fn apply_hits(
+	mut commands: Commands,
+	mut hits: EventReader<HitEvent>,
+	mut deaths: EventWriter<DeathEvent>,
+	mut hit_stop: ResMut<HitStop>,
+	mut target_query: Query<(&mut Health, Option<&mut InvincibilityTimer>, Option<&Armour>, Option<&Player>, Option<&ScriptedDeath>, Option<&Brick>)>,
+) {
+	for hit in hits.iter() {
+		let (mut health, inv_timer, armour, player, scripted, brick) = match target_query.get_mut(hit.target) {
+			Ok(target) => target,
+			Err(_) => continue,
+		};
+		//already dead, just not gone yet
+		if health.health <= 0. {
+			continue;
+		}
+		if let Some(mut inv_timer) = inv_timer {
+			if !inv_timer.finished() {
+				continue;
+			}
+			inv_timer.reset();
+		}
+		health.health -= hit.damage * armour.map_or(1., |armour| armour.0);
+		info!("{}", health.health);
+		//mining is too frequent to freeze the world for
+		if brick.is_none() {
+			hit_stop.0.reset();
+		}
+
+		if health.health <= 0. {
+			if scripted.is_some() {
+				continue;
+			}
+			if player.is_some() {
+				commands.insert_resource(NextState(GameState::GameOver));
+			}
+			deaths.send(DeathEvent { target: hit.target });
+			commands.entity(hit.target).despawn();
+		} else if hit.knockback != Vec2::ZERO {
+			commands.entity(hit.target).insert(Knockback(hit.knockback));
+		}
+	}
+}
+
+fn tick_hit_stop(time: Res<Time>, mut hit_stop: ResMut<HitStop>) {
+	hit_stop.0.tick(time.delta());
+}
+
+fn blocked(pos: Vec3, walls: &Query<&Transform, (With<Collider>, Without<Knockback>)>) -> bool {
+	walls.iter().any(|wall| collide(pos, Vec2::splat(KNOCKBACK_SIZE), wall.translation, Vec2::splat(TILE_SIZE)).is_some())
+}
+
+// Slides knocked back things along, stopping at walls, until the shove wears off
+fn apply_knockback(
+	mut commands: Commands,
+	time: Res<Time>,
+	walls: Query<&Transform, (With<Collider>, Without<Knockback>)>,
+	mut knocked: Query<(Entity, &mut Transform, &mut Knockback)>,
+) {
+	let dt = time.delta_seconds();
+	for (entity, mut transform, mut knockback) in knocked.iter_mut() {
+		for step in [Vec3::new(knockback.0.x * dt, 0., 0.), Vec3::new(0., knockback.0.y * dt, 0.)] {
+			if !blocked(transform.translation + step, &walls) {
+				transform.translation += step;
+			}
+		}
+		knockback.0 *= (1. - KNOCKBACK_DRAG * dt).max(0.);
+		if knockback.0.length() < TILE_SIZE {
+			commands.entity(entity).remove::<Knockback>();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn knockback_pushes_away_and_up() {
+		let target = Entity::from_raw(0);
+		let left = HitEvent::from_point(target, 10., 100., Vec3::new(50., 0., 0.), Vec3::ZERO);
+		assert!(left.knockback.x < 0. && left.knockback.y > 0.);
+		assert!((left.knockback.length() - 100.).abs() < 0.01);
+
+		let right = HitEvent::from_point(target, 10., 100., Vec3::new(-50., 20., 0.), Vec3::ZERO);
+		assert!(right.knockback.x > 0. && right.knockback.y > 0.);
+	}
+}
@@ -0,0 +1,271 @@
+use std::collections::VecDeque;
+#[cfg(test)]
+use std::collections::HashSet;
+
+use crate::{
+	ROOM_WIDTH,
+	ROOM_HEIGHT,
+	level::{
+		Map,
+		Room,
+		is_solid,
+		LEFT,
+		RIGHT,
+		TOP,
+		BOTTOM,
+	},
+};
+
+pub fn opposite(side: usize) -> usize {
+	match side {
+		LEFT => RIGHT,
+		RIGHT => LEFT,
+		TOP => BOTTOM,
+		_ => TOP,
+	}
+}
+
+// map coords of the room on the other side of `side`
+pub fn neighbour(x: i32, y: i32, side: usize) -> (i32, i32) {
+	match side {
+		LEFT => (x - 1, y),
+		RIGHT => (x + 1, y),
+		TOP => (x, y - 1),
+		_ => (x, y + 1),
+	}
+}
+
+// two rooms are linked only if both of them exist and have the shared exit open
+#[cfg(test)]
+fn linked(map: &Map, x: i32, y: i32, side: usize) -> Option<(i32, i32)> {
+	let (nx, ny) = neighbour(x, y, side);
+	let here = map.room(x, y)?;
+	let there = map.room(nx, ny)?;
+	if here.exits[side] && there.exits[opposite(side)] {
+		Some((nx, ny))
+	} else {
+		None
+	}
+}
+
+// Which of the generated rooms the player can walk to from the start room
+#[cfg(test)]
+pub fn reachable_rooms(map: &Map) -> HashSet<(i32, i32)> {
+	let mut reachable = HashSet::from([(0, 0)]);
+	let mut queue = VecDeque::from([(0, 0)]);
+	while let Some((x, y)) = queue.pop_front() {
+		for side in [LEFT, RIGHT, TOP, BOTTOM] {
+			if let Some(next) = linked(map, x, y, side) {
+				if reachable.insert(next) {
+					queue.push_back(next);
+				}
+			}
+		}
+	}
+	reachable
+}
+
+pub fn side_name(side: usize) -> &'static str {
+	match side {
+		LEFT => "left",
+		RIGHT => "right",
+		TOP => "top",
+		_ => "bottom",
+	}
+}
+
+// What fitting a newly generated room into the map had to change
+#[derive(Debug, PartialEq)]
+pub struct RoomReport {
+	pub room: (i32, i32),
+	pub mismatched_sides: Vec<usize>, //exits that disagreed with the room already next door, now matching it
+	pub tiles_carved: usize,
+}
+
+// Opens or walls up one side of a room, the unbreakable border is what keeps a closed side shut
+fn set_exit(room: &mut Room, side: usize, open: bool) {
+	room.exits[side] = open;
+	for (i, j) in side_cells(side) {
+		let tile = &mut room.room_coords[i][j];
+		if !open {
+			*tile = 'U';
+		} else if *tile == 'U' {
+			*tile = '#';
+		}
+	}
+}
+
+// Fits a room that's about to be added at (x, y) to the rooms already around it. Rooms already
+// generated win, so any exit of the new room that disagrees with its neighbour is changed to match,
+// then tunnels are carved until its open exits all join up.
+pub fn fit_room(map: &Map, x: i32, y: i32, room: &mut Room) -> RoomReport {
+	let mut mismatched_sides = Vec::new();
+	for side in [LEFT, RIGHT, TOP, BOTTOM] {
+		let (nx, ny) = neighbour(x, y, side);
+		if let Some(there) = map.room(nx, ny) {
+			let open = there.exits[opposite(side)];
+			if room.exits[side] != open {
+				set_exit(room, side, open);
+				mismatched_sides.push(side);
+			}
+		}
+	}
+	let tiles_carved = repair_room(room);
+	RoomReport { room: (x, y), mismatched_sides, tiles_carved }
+}
+
+// border tiles on one side of the room, corners left out since they belong to two sides
+fn side_cells(side: usize) -> Vec<(usize, usize)> {
+	match side {
+		LEFT => (1..ROOM_HEIGHT-1).map(|i| (i, 0)).collect(),
+		RIGHT => (1..ROOM_HEIGHT-1).map(|i| (i, ROOM_WIDTH-1)).collect(),
+		TOP => (1..ROOM_WIDTH-1).map(|j| (0, j)).collect(),
+		_ => (1..ROOM_WIDTH-1).map(|j| (ROOM_HEIGHT-1, j)).collect(),
+	}
+}
+
+// labels each open tile with the id of the pocket it's in
+fn label_pockets(room: &Room) -> [[usize;ROOM_WIDTH]; ROOM_HEIGHT] {
+	let mut labels = [[0;ROOM_WIDTH]; ROOM_HEIGHT];
+	let mut next = 1;
+	for i in 0..ROOM_HEIGHT {
+		for j in 0..ROOM_WIDTH {
+			if labels[i][j] != 0 || is_solid(room.room_coords[i][j]) {
+				continue;
+			}
+			let mut queue = VecDeque::from([(i, j)]);
+			labels[i][j] = next;
+			while let Some((ci, cj)) = queue.pop_front() {
+				let around = [
+					(ci.wrapping_sub(1), cj),
+					(ci + 1, cj),
+					(ci, cj.wrapping_sub(1)),
+					(ci, cj + 1),
+				];
+				for (ni, nj) in around {
+					if ni < ROOM_HEIGHT && nj < ROOM_WIDTH && labels[ni][nj] == 0 && !is_solid(room.room_coords[ni][nj]) {
+						labels[ni][nj] = next;
+						queue.push_back((ni, nj));
+					}
+				}
+			}
+			next += 1;
+		}
+	}
+	labels
+}
+
+// the tile just inside the room from a border tile
+fn inward((i, j): (usize, usize)) -> (usize, usize) {
+	(i.clamp(1, ROOM_HEIGHT-2), j.clamp(1, ROOM_WIDTH-2))
+}
+
+// digs an L shaped tunnel between two tiles, staying off the room border
+fn carve(room: &mut Room, from: (usize, usize), to: (usize, usize)) -> usize {
+	let (a, b) = (inward(from), inward(to));
+	let mut path = vec![a, b];
+	for j in a.1.min(b.1)..=a.1.max(b.1) {
+		path.push((a.0, j));
+	}
+	for i in a.0.min(b.0)..=a.0.max(b.0) {
+		path.push((i, b.1));
+	}
+	let mut carved = 0;
+	for (i, j) in path {
+		if is_solid(room.room_coords[i][j]) {
+			room.room_coords[i][j] = '-';
+			carved += 1;
+		}
+	}
+	carved
+}
+
+// Carves tunnels until every open exit of the room is in the same pocket, returns how many tiles were dug out
+pub fn repair_room(room: &mut Room) -> usize {
+	let mut carved = 0;
+	let mut anchors = Vec::new();
+	for side in [LEFT, RIGHT, TOP, BOTTOM] {
+		if !room.exits[side] {
+			continue;
+		}
+		let cells = side_cells(side);
+		let anchor = match cells.iter().find(|(i, j)| !is_solid(room.room_coords[*i][*j])) {
+			Some(cell) => *cell,
+			None => {
+				//exit is marked open but walled in, knock out the middle of it
+				let (i, j) = cells[cells.len() / 2];
+				room.room_coords[i][j] = '-';
+				carved += 1;
+				(i, j)
+			}
+		};
+		anchors.push(anchor);
+	}
+
+	if let Some((root, rest)) = anchors.split_first() {
+		for anchor in rest {
+			let labels = label_pockets(room);
+			if labels[anchor.0][anchor.1] != labels[root.0][root.1] {
+				carved += carve(room, *anchor, *root);
+			}
+		}
+	}
+	carved
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::room_file::bundled_templates;
+
+	#[test]
+	fn new_rooms_match_their_neighbours_and_reopen_blocked_exits() {
+		let mut map = Map::new(99, bundled_templates());
+		map.room_or_generate(1, 1);
+
+		//a room split down the middle is carved through
+		let mut room = Room::new([true, true, false, false]);
+		for row in room.room_coords.iter_mut() {
+			row[ROOM_WIDTH / 2] = '#';
+		}
+		room.room_coords[0] = ['U'; ROOM_WIDTH];
+		room.room_coords[ROOM_HEIGHT - 1] = ['U'; ROOM_WIDTH];
+		let report = fit_room(&map, 50, 50, &mut room);
+		assert!(report.mismatched_sides.is_empty() && report.tiles_carved > 0);
+		assert_eq!(fit_room(&map, 50, 50, &mut room).tiles_carved, 0);
+
+		//next to an existing room it takes that room's side of the edge
+		let open = map.room(1, 1).unwrap().exits[RIGHT];
+		let mut room = Room::new([!open, true, true, true]);
+		let report = fit_room(&map, 2, 1, &mut room);
+		assert_eq!(report.room, (2, 1));
+		assert_eq!(report.mismatched_sides, vec![LEFT]);
+		assert_eq!(room.exits[LEFT], open);
+		assert_eq!(side_cells(LEFT).iter().all(|(i, j)| room.room_coords[*i][*j] == 'U'), !open);
+	}
+
+	#[test]
+	fn generated_rooms_agree_with_neighbours_and_connect_their_exits() {
+		let mut map = Map::new(99, bundled_templates());
+		for y in -6..6 {
+			for x in -6..6 {
+				map.room_or_generate(x, y);
+			}
+		}
+		for ((x, y), room) in map.rooms() {
+			for side in [LEFT, RIGHT, TOP, BOTTOM] {
+				let (nx, ny) = neighbour(*x, *y, side);
+				if let Some(next_door) = map.room(nx, ny) {
+					assert_eq!(room.exits[side], next_door.exits[opposite(side)]);
+				}
+			}
+			let labels = label_pockets(room);
+			let pockets: Vec<usize> = [LEFT, RIGHT, TOP, BOTTOM].into_iter()
+				.filter(|side| room.exits[*side])
+				.filter_map(|side| side_cells(side).into_iter().find(|(i, j)| !is_solid(room.room_coords[*i][*j])))
+				.map(|(i, j)| labels[i][j])
+				.collect();
+			assert!(pockets.windows(2).all(|w| w[0] == w[1]));
+		}
+	}
+}
@@ -0,0 +1,355 @@
+use std::{
+	collections::{BTreeMap, HashSet},
+	fs,
+	marker::PhantomData,
+};
+
+use bevy::{
+	ecs::system::SystemParam,
+	input::InputSystem,
+	prelude::*,
+};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	WIN_H,
+	GameState,
+};
+
+/*
+Controls are saved to controls.ron next to the game, as a list of bindings per action:
+
+	{
+		Jump: [Key(Space), Button(South)],
+		DigDown: [Key(S), Button(DPadDown)],
+		...
+	}
+
+Key names are bevy KeyCodes and button names are GamepadButtonTypes. Actions missing from the file
+keep their default bindings. Press F1 on the main menu to rebind them one after another, the file is
+rewritten once the last one is set.
+*/
+
+const CONTROLS_PATH: &str = "controls.ron";
+const STICK_DEADZONE: f32 = 0.5; //how far the left stick has to lean to count as a direction
+const REBIND_KEY: KeyCode = KeyCode::F1;
+const SKIP_KEY: KeyCode = KeyCode::Escape; //keeps the current binding while rebinding
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+	MoveLeft,
+	MoveRight,
+	Jump,
+	Swing,
+	Throw,
+	Interact,
+	DigDown,
+	AimUp,
+}
+
+impl Action {
+	pub const ALL: [Action; 8] = [
+		Action::MoveLeft,
+		Action::MoveRight,
+		Action::Jump,
+		Action::Swing,
+		Action::Throw,
+		Action::Interact,
+		Action::DigDown,
+		Action::AimUp,
+	];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+	Key(KeyCode),
+	Button(GamepadButtonType),
+}
+
+impl Binding {
+	fn same_device(&self, other: &Binding) -> bool {
+		matches!((self, other), (Binding::Key(_), Binding::Key(_)) | (Binding::Button(_), Binding::Button(_)))
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bindings(BTreeMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+	fn default() -> Self {
+		use Binding::*;
+		Self(BTreeMap::from([
+			(Action::MoveLeft, vec![Key(KeyCode::A), Key(KeyCode::Left), Button(GamepadButtonType::DPadLeft)]),
+			(Action::MoveRight, vec![Key(KeyCode::D), Key(KeyCode::Right), Button(GamepadButtonType::DPadRight)]),
+			(Action::Jump, vec![Key(KeyCode::Space), Button(GamepadButtonType::South)]),
+			(Action::Swing, vec![Key(KeyCode::E), Button(GamepadButtonType::West)]),
+			(Action::Throw, vec![Key(KeyCode::F), Button(GamepadButtonType::East)]),
+			(Action::Interact, vec![Key(KeyCode::W), Button(GamepadButtonType::North)]),
+			(Action::DigDown, vec![Key(KeyCode::S), Button(GamepadButtonType::DPadDown)]),
+			(Action::AimUp, vec![Key(KeyCode::Up), Button(GamepadButtonType::DPadUp)]),
+		]))
+	}
+}
+
+impl Bindings {
+	pub fn get(&self, action: Action) -> &[Binding] {
+		self.0.get(&action).map_or(&[], |bindings| bindings.as_slice())
+	}
+
+	// Replaces the action's first binding on the same device, and takes the binding off
+	// anything else that was using it
+	pub fn rebind(&mut self, action: Action, binding: Binding) {
+		for bindings in self.0.values_mut() {
+			bindings.retain(|b| *b != binding);
+		}
+		let bindings = self.0.entry(action).or_default();
+		match bindings.iter().position(|b| b.same_device(&binding)) {
+			Some(i) => bindings[i] = binding,
+			None => bindings.push(binding),
+		}
+	}
+
+	fn parse(text: &str) -> Result<Self, ron::Error> {
+		let mut bindings = Self::default();
+		let saved: BTreeMap<Action, Vec<Binding>> = ron::from_str(text)?;
+		bindings.0.extend(saved);
+		Ok(bindings)
+	}
+
+	// Saved bindings, or the defaults if there aren't any yet
+	fn load() -> Self {
+		match fs::read_to_string(CONTROLS_PATH) {
+			Ok(text) => Self::parse(&text).unwrap_or_else(|e| {
+				warn!("couldn't read {}, using default controls: {}", CONTROLS_PATH, e);
+				Self::default()
+			}),
+			Err(_) => Self::default(),
+		}
+	}
+
+	fn save(&self) {
+		let saved = ron::ser::to_string_pretty(&self.0, ron::ser::PrettyConfig::default())
+			.map_err(anyhow::Error::from)
+			.and_then(|text| fs::write(CONTROLS_PATH, text).map_err(anyhow::Error::from));
+		match saved {
+			Ok(()) => info!("saved controls to {}", CONTROLS_PATH),
+			Err(e) => warn!("couldn't save controls to {}: {}", CONTROLS_PATH, e),
+		}
+	}
+}
+
+// Read actions instead of keys, covers the keyboard and every connected gamepad
+#[derive(SystemParam)]
+pub struct Actions<'w, 's> {
+	bindings: Res<'w, Bindings>,
+	rebinding: Option<Res<'w, Rebinding>>,
+	keys: Res<'w, Input<KeyCode>>,
+	buttons: Res<'w, Input<GamepadButton>>,
+	axes: Res<'w, Axis<GamepadAxis>>,
+	gamepads: Res<'w, Gamepads>,
+	#[system_param(ignore)]
+	marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Actions<'w, 's> {
+	pub fn pressed(&self, action: Action) -> bool {
+		self.check(action, |keys, key| keys.pressed(key), |buttons, button| buttons.pressed(button))
+			|| self.stick(action)
+	}
+
+	pub fn just_pressed(&self, action: Action) -> bool {
+		self.check(action, |keys, key| keys.just_pressed(key), |buttons, button| buttons.just_pressed(button))
+	}
+
+	fn check(
+		&self,
+		action: Action,
+		key_state: impl Fn(&Input<KeyCode>, KeyCode) -> bool,
+		button_state: impl Fn(&Input<GamepadButton>, GamepadButton) -> bool,
+	) -> bool {
+		//keys pressed while rebinding belong to the rebinding
+		if self.rebinding.is_some() {
+			return false;
+		}
+		self.bindings.get(action).iter().any(|binding| match binding {
+			Binding::Key(key) => key_state(&self.keys, *key),
+			Binding::Button(button) => self.gamepads.iter()
+				.any(|gamepad| button_state(&self.buttons, GamepadButton(*gamepad, *button))),
+		})
+	}
+
+	// The left stick always moves and aims, whatever the bindings say
+	fn stick(&self, action: Action) -> bool {
+		if self.rebinding.is_some() {
+			return false;
+		}
+		let lean = |axis: GamepadAxisType, sign: f32| self.gamepads.iter()
+			.any(|gamepad| self.axes.get(GamepadAxis(*gamepad, axis)).unwrap_or(0.) * sign > STICK_DEADZONE);
+		match action {
+			Action::MoveLeft => lean(GamepadAxisType::LeftStickX, -1.),
+			Action::MoveRight => lean(GamepadAxisType::LeftStickX, 1.),
+			Action::AimUp => lean(GamepadAxisType::LeftStickY, 1.),
+			Action::DigDown => lean(GamepadAxisType::LeftStickY, -1.),
+			_ => false,
+		}
+	}
+}
+
+// Actions pressed since the fixed step last ran. The fixed step can go a frame without running, or
+// sit out a hit-stop, so a just_pressed read inside it would miss presses that came in meanwhile
+#[derive(Default)]
+pub struct StepPresses(HashSet<Action>);
+
+impl StepPresses {
+	pub fn pressed(&self, action: Action) -> bool {
+		self.0.contains(&action)
+	}
+}
+
+fn record_presses(actions: Actions, mut presses: ResMut<StepPresses>) {
+	for action in Action::ALL {
+		if actions.just_pressed(action) {
+			presses.0.insert(action);
+		}
+	}
+}
+
+// Goes at the end of the fixed step, once everything in it has had a look
+pub fn clear_step_presses(mut presses: ResMut<StepPresses>) {
+	presses.0.clear();
+}
+
+// Waiting for a new key or button for the nth action in Action::ALL
+pub struct Rebinding(usize);
+
+#[derive(Component)]
+struct RebindPrompt;
+
+struct PromptFont(Handle<Font>);
+
+pub struct ControlsPlugin;
+impl Plugin for ControlsPlugin {
+	fn build (&self, app: &mut App) {
+		app.insert_resource(Bindings::load())
+			.init_resource::<StepPresses>()
+			.add_system_to_stage(CoreStage::PreUpdate, record_presses.after(InputSystem))
+			.add_startup_system(load_prompt_font)
+			.add_system(start_rebinding.run_in_state(GameState::MainMenu))
+			.add_system(rebind.run_if_resource_exists::<Rebinding>())
+			.add_system(show_prompt);
+	}
+}
+
+fn load_prompt_font(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands.insert_resource(PromptFont(asset_server.load("quattrocentosans-bold.ttf")));
+}
+
+fn start_rebinding(mut commands: Commands, keys: Res<Input<KeyCode>>, rebinding: Option<Res<Rebinding>>) {
+	if keys.just_pressed(REBIND_KEY) && rebinding.is_none() {
+		commands.insert_resource(Rebinding(0));
+	}
+}
+
+fn rebind(
+	mut commands: Commands,
+	mut rebinding: ResMut<Rebinding>,
+	mut bindings: ResMut<Bindings>,
+	keys: Res<Input<KeyCode>>,
+	buttons: Res<Input<GamepadButton>>,
+) {
+	let action = Action::ALL[rebinding.0];
+	let pressed = keys.get_just_pressed()
+		.find(|key| **key != REBIND_KEY)
+		.map(|key| Binding::Key(*key))
+		.or_else(|| buttons.get_just_pressed().next().map(|button| Binding::Button(button.1)));
+	match pressed {
+		Some(Binding::Key(SKIP_KEY)) => {}
+		Some(binding) => {
+			info!("bound {:?} to {:?}", action, binding);
+			bindings.rebind(action, binding);
+		}
+		None => return,
+	}
+	rebinding.0 += 1;
+	if rebinding.0 == Action::ALL.len() {
+		bindings.save();
+		commands.remove_resource::<Rebinding>();
+	}
+}
+
+// Keeps a line of text at the top of the screen while rebinding, asking for the next action
+fn show_prompt(
+	mut commands: Commands,
+	font: Res<PromptFont>,
+	rebinding: Option<Res<Rebinding>>,
+	mut prompt_query: Query<(Entity, &mut Text), With<RebindPrompt>>,
+) {
+	let rebinding = match rebinding {
+		Some(rebinding) => rebinding,
+		None => {
+			for (entity, _) in prompt_query.iter() {
+				commands.entity(entity).despawn();
+			}
+			return;
+		}
+	};
+	let message = format!("Press a key or button for {:?}  (Esc keeps the current one)", Action::ALL[rebinding.0]);
+	match prompt_query.get_single_mut() {
+		Ok((_, mut text)) => text.sections[0].value = message,
+		Err(_) => {
+			commands
+				.spawn_bundle(Text2dBundle {
+					text: Text::with_section(
+						message,
+						TextStyle {
+							font: font.0.clone(),
+							font_size: 30.0,
+							color: Color::WHITE,
+						},
+						TextAlignment {
+							vertical: VerticalAlign::Center,
+							horizontal: HorizontalAlign::Center,
+						},
+					),
+					transform: Transform::from_xyz(0., WIN_H / 2. - 40., 999.),
+					..default()
+				})
+				.insert(RebindPrompt);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn default_bindings_are_all_different() {
+		let bindings = Bindings::default();
+		let all: Vec<Binding> = Action::ALL.iter().flat_map(|action| bindings.get(*action).iter().copied()).collect();
+		for (i, binding) in all.iter().enumerate() {
+			assert!(!all[i+1..].contains(binding), "{:?} is bound twice", binding);
+		}
+	}
+
+	#[test]
+	fn rebinding_moves_a_key_between_actions() {
+		let mut bindings = Bindings::default();
+		bindings.rebind(Action::Jump, Binding::Key(KeyCode::E));
+		assert_eq!(bindings.get(Action::Jump)[0], Binding::Key(KeyCode::E));
+		assert!(!bindings.get(Action::Swing).contains(&Binding::Key(KeyCode::E)));
+		//the pad binding isn't touched by a key
+		assert!(bindings.get(Action::Jump).contains(&Binding::Button(GamepadButtonType::South)));
+	}
+
+	#[test]
+	fn saved_controls_round_trip() {
+		let mut bindings = Bindings::default();
+		bindings.rebind(Action::Throw, Binding::Button(GamepadButtonType::RightTrigger));
+		let text = ron::ser::to_string_pretty(&bindings.0, ron::ser::PrettyConfig::default()).unwrap();
+		assert_eq!(Bindings::parse(&text).unwrap(), bindings);
+		//older files missing an action keep its default
+		assert_eq!(Bindings::parse("{Jump: [Key(Up)]}").unwrap().get(Action::Swing), Bindings::default().get(Action::Swing));
+	}
+}
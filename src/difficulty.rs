@@ -0,0 +1,75 @@
+// How hard a room is comes from a single "danger" number, built from how deep the room is and
+// how far it is from the start room. The curve below turns danger into spawn odds and stats,
+// interpolating between rows, so tuning the whole run happens in this file.
+
+const DEPTH_WEIGHT: f32 = 1.5; //danger per room below the start room
+const DISTANCE_WEIGHT: f32 = 0.5; //danger per room away from the start room, in any direction
+
+// Spawn odds are "1 in this many open tiles", stats are multipliers on the base values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Difficulty {
+	pub enemy_odds: f32,
+	pub bomb_odds: f32,
+	pub door_odds: f32,
+	pub heart_odds: f32,
+	pub pickaxe_odds: f32,
+	pub trap_odds: f32, //per brick facing open space
+	pub nest_odds: f32, //per brick facing open space
+	pub brick_health: f32,
+	pub enemy_health: f32,
+	pub enemy_damage: f32,
+	pub enemy_speed: f32,
+}
+
+//(danger, difficulty at that danger), sorted by danger
+const DIFFICULTY_CURVE: [(f32, Difficulty); 4] = [
+	(0., Difficulty { enemy_odds: 35., bomb_odds: 30., door_odds: 100., heart_odds: 50., pickaxe_odds: 800., trap_odds: 200., nest_odds: 600., brick_health: 1.0, enemy_health: 1.0, enemy_damage: 1.0, enemy_speed: 1.0 }),
+	(5., Difficulty { enemy_odds: 25., bomb_odds: 32., door_odds: 110., heart_odds: 60., pickaxe_odds: 600., trap_odds: 60., nest_odds: 250., brick_health: 1.25, enemy_health: 1.25, enemy_damage: 1.25, enemy_speed: 1.1 }),
+	(12., Difficulty { enemy_odds: 17., bomb_odds: 36., door_odds: 130., heart_odds: 75., pickaxe_odds: 450., trap_odds: 35., nest_odds: 150., brick_health: 1.6, enemy_health: 1.6, enemy_damage: 1.5, enemy_speed: 1.25 }),
+	(25., Difficulty { enemy_odds: 11., bomb_odds: 40., door_odds: 160., heart_odds: 95., pickaxe_odds: 350., trap_odds: 25., nest_odds: 100., brick_health: 2.0, enemy_health: 2.2, enemy_damage: 2.0, enemy_speed: 1.4 }),
+];
+
+pub fn danger(x: i32, y: i32) -> f32 {
+	let depth = y.max(0) as f32;
+	let distance = (x.unsigned_abs() + y.unsigned_abs()) as f32;
+	DEPTH_WEIGHT * depth + DISTANCE_WEIGHT * distance
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+	a + (b - a) * t
+}
+
+impl Difficulty {
+	fn lerp(&self, other: &Difficulty, t: f32) -> Difficulty {
+		Difficulty {
+			enemy_odds: lerp(self.enemy_odds, other.enemy_odds, t),
+			bomb_odds: lerp(self.bomb_odds, other.bomb_odds, t),
+			door_odds: lerp(self.door_odds, other.door_odds, t),
+			heart_odds: lerp(self.heart_odds, other.heart_odds, t),
+			pickaxe_odds: lerp(self.pickaxe_odds, other.pickaxe_odds, t),
+			trap_odds: lerp(self.trap_odds, other.trap_odds, t),
+			nest_odds: lerp(self.nest_odds, other.nest_odds, t),
+			brick_health: lerp(self.brick_health, other.brick_health, t),
+			enemy_health: lerp(self.enemy_health, other.enemy_health, t),
+			enemy_damage: lerp(self.enemy_damage, other.enemy_damage, t),
+			enemy_speed: lerp(self.enemy_speed, other.enemy_speed, t),
+		}
+	}
+
+	// rolls a 1 in `odds` chance
+	pub fn roll(odds: f32, rng: &mut impl rand::Rng) -> bool {
+		rng.gen_range(0..(odds.round() as u32).max(1)) == 0
+	}
+}
+
+// Difficulty for the room at the given map coords, past the end of the curve it stays flat
+pub fn difficulty_at(x: i32, y: i32) -> Difficulty {
+	let danger = danger(x, y);
+	for pair in DIFFICULTY_CURVE.windows(2) {
+		let ((from, low), (to, high)) = (pair[0], pair[1]);
+		if danger < to {
+			return low.lerp(&high, ((danger - from) / (to - from)).max(0.));
+		}
+	}
+	DIFFICULTY_CURVE[DIFFICULTY_CURVE.len() - 1].1
+}
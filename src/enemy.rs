@@ -2,6 +2,7 @@ use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 use bevy::sprite::collide_aabb::collide;
 
+use rand::random;
 use std::f32::consts::PI;
 
 use crate::{
@@ -10,53 +11,65 @@ use crate::{
 	BASE_SPEED,
 	TILE_SIZE,
 	level::Collider,
-	loading::{
-		LoadingAssets,
-		LoadingAssetInfo,
+	player::Player,
+	ai::{
+		AiState,
+		Brain,
 	},
+	combat::hit_stop_over,
 };
 
 #[derive(Component)]
 pub struct Enemy;
 
-#[derive(Deref, DerefMut)]
-pub struct EnemySheet(Handle<TextureAtlas>);
+const BAT_SIZE: f32 = 50.;
+const BAT_RADIUS: (f32, f32) = (1.5 * TILE_SIZE, TILE_SIZE); //size of the loop a bat flies around its spawn
+
+const DIVE_SPEED: f32 = 1.5; //how much faster a bat moves when it attacks
+
+// Each bat loops around its own spawn point, starting somewhere random on the loop
+#[derive(Component)]
+pub struct Bat {
+	pivot: Vec2,
+	angle: f32,
+	dir: f32, //1 counter clockwise, -1 clockwise
+}
+
+impl Bat {
+	pub fn new(spawn: Vec3) -> Self {
+		//flies out from its spawn tile to its own spot on the loop
+		Self {
+			pivot: spawn.truncate(),
+			angle: random::<f32>() * 2. * PI,
+			dir: if random::<bool>() { 1. } else { -1. },
+		}
+	}
+}
+
+// Per-enemy numbers, scaled by the room's difficulty when it's spawned
+#[derive(Component)]
+pub struct EnemyStats {
+	pub damage: f32, //taken from the player on contact
+	pub knockback: f32, //how hard that contact shoves them, px per second
+	pub speed: f32, //multiplier on BASE_SPEED
+}
 
 pub struct EnemyPlugin;
 impl Plugin for EnemyPlugin {
 	fn build (&self, app: &mut App) {
-		app.add_enter_system(GameState::Loading, load_enemy_sheet)
-		.add_system(enemy_movement_system);
+		app.add_system(enemy_movement_system.run_in_state(GameState::Playing).run_if(hit_stop_over));
 		
 	}
 }
 
-fn load_enemy_sheet(
-	mut commands: Commands,
-	asset_server: Res<AssetServer>,
-	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-	mut loading_assets: ResMut<LoadingAssets>,
-) {
-	let enemy_handle = asset_server.load("bat.png");
-	loading_assets.insert(
-		enemy_handle.clone_untyped(),
-		LoadingAssetInfo::for_handle(enemy_handle.clone_untyped(), &asset_server),
-	);
-
-	let enemy_atlas = TextureAtlas::from_grid(enemy_handle, Vec2::splat(70.), 25, 25);
-	let enemy_atlas_handle = texture_atlases.add(enemy_atlas);
-	
-	commands.insert_resource(EnemySheet(enemy_atlas_handle));
-}
-
 fn check_tile_collision(
 	pos: Vec3,
-	wall_collide: &Query<&Transform, (With<Collider>, Without<Enemy>)>
+	wall_collide: &Query<&Transform, (With<Collider>, Without<Bat>)>
 ) -> bool{
 	for wall in wall_collide.iter(){
 		let collision = collide(
 			pos,
-			Vec2::splat(TILE_SIZE * 0.9),
+			Vec2::splat(BAT_SIZE),
 			wall.translation,
 			Vec2::splat(TILE_SIZE)
 		);
@@ -67,41 +80,57 @@ fn check_tile_collision(
 	true
 }
 
-fn enemy_movement_system(time: Res<Time>, mut query: Query<&mut Transform, With<Enemy>>){
-	let now = time.seconds_since_startup() as f32;
-	for mut transform in query.iter_mut(){
+fn enemy_movement_system(
+	time: Res<Time>,
+	collision: Query<&Transform, (With<Collider>, Without<Bat>)>,
+	player_query: Query<&Transform, (With<Player>, Without<Bat>)>,
+	mut query: Query<(&mut Transform, &mut Bat, &EnemyStats, &Brain)>,
+){
+	let player = player_query.get_single().map(|p| p.translation.truncate()).ok();
+	for (mut transform, mut bat, stats, brain) in query.iter_mut(){
 		//current position
 		let (x_org, y_org) = (transform.translation.x, transform.translation.y);
+		let here = Vec2::new(x_org, y_org);
 
 		//max distance
-		let max_distance = TIME_STEP * BASE_SPEED;
-
-		//fixtures (hardcoded)
-		let dir: f32 = 1.; //1 counter clockwise, -1 clockwise
-		let (x_pivot, y_pivot) = (0., 0.);
-		let (x_radius, y_radius) = (200., 130.);
-
-		//compute path
-		let angle = dir * BASE_SPEED * TIME_STEP * now % 360./ PI;
+		let mut max_distance = TIME_STEP * BASE_SPEED * stats.speed;
 
 		// compute target x/y
-		let x_dst = x_radius * angle.cos() + x_pivot;
-		let y_dst = y_radius * angle.sin() + y_pivot;
+		let (x_dst, y_dst) = match (brain.state, player) {
+			(AiState::Chase, Some(player)) => (player.x, player.y),
+			(AiState::Attack, Some(player)) => {
+				max_distance *= DIVE_SPEED;
+				(player.x, player.y)
+			}
+			(AiState::Flee, Some(player)) => {
+				let away = here + (here - player).normalize_or_zero() * TILE_SIZE;
+				(away.x, away.y)
+			}
+			(AiState::Patrol, _) => {
+				//move along the bat's own loop around where it spawned
+				bat.angle += bat.dir * BASE_SPEED * TIME_STEP / PI * stats.speed * time.delta_seconds();
+				let (x_radius, y_radius) = BAT_RADIUS;
+				(x_radius * bat.angle.cos() + bat.pivot.x, y_radius * bat.angle.sin() + bat.pivot.y)
+			}
+			//hovers in place
+			_ => (x_org, y_org),
+		};
 
 		//compute distance
 		let dx = x_org - x_dst;
 		let dy = y_org - y_dst;
 		let distance = (dx*dx + dy*dy).sqrt();
-		let distance_ratio = if distance != 0. {max_distance / distance} else {0.};
+		let distance_ratio = if distance != 0. {(max_distance / distance).min(1.)} else {0.};
 
 		//compute final
-		let x = x_org - dx * distance_ratio;
-		let x = if dx > 0. {x.max(x_dst)} else {x.min(x_dst)};
-		let y = y_org - dy * distance_ratio;
-		let y = if dy > 0. {y.max(x_dst)} else {y.min(x_dst)};
-
-		let translation = &mut transform.translation;
-		(translation.x, translation.y) = (x,y);
-
+		let step = Vec3::new(-dx * distance_ratio, -dy * distance_ratio, 0.);
+
+		//slide along walls, and if it can't move at all turn around
+		let moves = [step, Vec3::new(step.x, 0., 0.), Vec3::new(0., step.y, 0.)];
+		match moves.iter().find(|m| m.length() > 0. && check_tile_collision(transform.translation + **m, &collision)) {
+			Some(m) => transform.translation += *m,
+			None if distance > 0. => bat.dir = -bat.dir,
+			None => {}
+		}
 	}
-}
\ No newline at end of file
+}
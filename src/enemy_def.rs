@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use bevy::{
+	asset::{AssetLoader, BoxedFuture, HandleId, LoadContext, LoadedAsset},
+	ecs::system::EntityCommands,
+	prelude::*,
+	reflect::TypeUuid,
+};
+use iyes_loopless::prelude::*;
+use rand::random;
+use serde::Deserialize;
+
+use crate::{
+	TILE_SIZE,
+	GameState,
+	loading::{
+		LoadingAssets,
+		LoadingAssetInfo,
+	},
+	level::{
+		BombItemSheet,
+		HealthItemSheet,
+		spawn_pickup,
+	},
+	difficulty::Difficulty,
+	player::Health,
+	enemy::{
+		Enemy,
+		EnemyStats,
+		Bat,
+	},
+	groundhog::Groundhog,
+	ai::{
+		Brain,
+		Senses,
+	},
+	nav::NavAgent,
+	combat::DeathEvent,
+};
+
+/*
+Enemy types live in assets/enemies/<name>.enemy, one RON file each:
+
+	(
+		name: "bat",
+		tile: 'E',                 room tile that spawns it, E, G or any of a-z
+		sprite: (
+			sheet: "bat.png",
+			hurt_sheet: None,      optional sheet shown for a moment after it's hit
+			frame: (70.0, 70.0),   size of one frame in pixels
+			columns: 25,
+			rows: 25,
+			frames: 1,             how many frames its animation cycles through
+		),
+		health: 100.0,
+		contact_damage: 20.0,      taken from the player on touch
+		contact_knockback: 8.0,    optional, how hard it shoves the player in tiles per second
+		speed: 1.0,                multiplier on the behaviour's base speed
+		behaviour: Bat,            Bat or Groundhog, which movement it uses
+		senses: (sight: 5.0, attack: 1.5, flee_below: 0.3, alert_time: 0.6), distances in tiles, see ai.rs
+		loot: [(item: 'B', chance: 0.15)], B bombs or H heart (anything else won't load), rolled separately when it dies
+	)
+
+Health, damage and speed are scaled by the room's difficulty when it spawns. Every file in the
+folder is picked up on start, and edits are picked up while the game runs.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Behaviour {
+	Bat,
+	Groundhog,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpriteDef {
+	pub sheet: String,
+	#[serde(default)]
+	pub hurt_sheet: Option<String>,
+	pub frame: (f32, f32),
+	pub columns: usize,
+	pub rows: usize,
+	pub frames: usize,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SensesDef {
+	pub sight: f32,
+	pub attack: f32,
+	pub flee_below: f32,
+	pub alert_time: f32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LootDef {
+	pub item: char,
+	pub chance: f32,
+}
+
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "9b1d4e7a-2c3f-4a8b-b5d6-7e8f9a0b1c2d"]
+pub struct EnemyDef {
+	pub name: String,
+	pub tile: char,
+	pub sprite: SpriteDef,
+	pub health: f32,
+	pub contact_damage: f32,
+	#[serde(default = "default_knockback")]
+	pub contact_knockback: f32,
+	pub speed: f32,
+	pub behaviour: Behaviour,
+	pub senses: SensesDef,
+	#[serde(default)]
+	pub loot: Vec<LootDef>,
+}
+
+fn default_knockback() -> f32 {
+	8.0
+}
+
+const LOOT_ITEMS: [char; 2] = ['B', 'H']; //bombs and hearts, the pickups spawn_pickup knows
+
+impl EnemyDef {
+	// Catches mistakes serde can't, so a bad file fails to load instead of misbehaving in game
+	fn check(&self) -> Result<(), String> {
+		//every other tile already means something in a room
+		if !(self.tile == 'E' || self.tile == 'G' || self.tile.is_ascii_lowercase()) {
+			return Err(format!("tile {:?} is taken by the level, use E, G or a-z", self.tile));
+		}
+		if let Some(loot) = self.loot.iter().find(|loot| !LOOT_ITEMS.contains(&loot.item)) {
+			return Err(format!("unknown loot item {:?}, expected one of {:?}", loot.item, LOOT_ITEMS));
+		}
+		Ok(())
+	}
+
+	pub fn senses(&self) -> Senses {
+		Senses {
+			sight: self.senses.sight * TILE_SIZE,
+			attack: self.senses.attack * TILE_SIZE,
+			flee_below: self.senses.flee_below,
+			alert_time: self.senses.alert_time,
+		}
+	}
+}
+
+#[derive(Default)]
+pub struct EnemyDefLoader;
+
+impl AssetLoader for EnemyDefLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let def: EnemyDef = ron::de::from_bytes(bytes)
+				.map_err(|e| anyhow::anyhow!("{}: {}", load_context.path().display(), e))?;
+			def.check().map_err(|e| anyhow::anyhow!("{}: {}", load_context.path().display(), e))?;
+			load_context.set_default_asset(LoadedAsset::new(def));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["enemy"]
+	}
+}
+
+// The atlases an enemy is drawn with, on the enemy so its behaviour can swap between them
+#[derive(Component, Clone)]
+pub struct EnemySprites {
+	pub normal: Handle<TextureAtlas>,
+	pub hurt: Option<Handle<TextureAtlas>>,
+	pub frames: usize,
+}
+
+pub struct EnemyKind {
+	pub def: EnemyDef,
+	pub sprites: EnemySprites,
+	source: HandleId, //the file it came from
+}
+
+// Every loaded enemy type by the room tile that spawns it
+#[derive(Default)]
+pub struct EnemyRoster(HashMap<char, EnemyKind>);
+
+impl EnemyRoster {
+	pub fn get(&self, tile: char) -> Option<&EnemyKind> {
+		self.0.get(&tile)
+	}
+}
+
+// What an enemy might drop when it's killed
+#[derive(Component, Clone)]
+pub struct Loot(pub Vec<LootDef>);
+
+// Loot and position of everything with loot as of last frame, for killing hits that land before drop_loot runs
+
+#[derive(Default)]
+struct LootTracker(HashMap<Entity, (Vec3, Vec<LootDef>)>);
+
+// Keeps the enemy files loaded, since nothing else holds their handles
+#[allow(dead_code)]
+struct EnemyDefs(Vec<HandleUntyped>);
+
+pub struct EnemyDefPlugin;
+impl Plugin for EnemyDefPlugin {
+	fn build (&self, app: &mut App) {
+		app.add_asset::<EnemyDef>()
+			.init_asset_loader::<EnemyDefLoader>()
+			.init_resource::<EnemyRoster>()
+			.init_resource::<LootTracker>()
+			.add_enter_system(GameState::Loading, load_enemy_defs)
+			.add_system(update_roster)
+			.add_system(drop_loot.run_in_state(GameState::Playing));
+	}
+}
+
+fn load_enemy_defs(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut loading_assets: ResMut<LoadingAssets>,
+) {
+	let handles = asset_server.load_folder("enemies").unwrap_or_else(|e| {
+		warn!("couldn't load enemy definitions: {:?}", e);
+		Vec::new()
+	});
+	for handle in handles.iter() {
+		loading_assets.insert(
+			handle.clone(),
+			LoadingAssetInfo::for_handle(handle.clone(), &asset_server),
+		);
+	}
+	commands.insert_resource(EnemyDefs(handles));
+}
+
+// Lays the sheet out as an atlas, into the one it had before if the file was edited so enemies
+// already in the room pick up the change
+fn load_sheet(path: &str, def: &EnemyDef, old: Option<&Handle<TextureAtlas>>, asset_server: &Res<AssetServer>, texture_atlases: &mut Assets<TextureAtlas>, loading_assets: &mut LoadingAssets) -> Handle<TextureAtlas> {
+	let handle: Handle<Image> = asset_server.load(path);
+	loading_assets.insert(
+		handle.clone_untyped(),
+		LoadingAssetInfo::for_handle(handle.clone_untyped(), asset_server),
+	);
+	let frame = Vec2::new(def.sprite.frame.0, def.sprite.frame.1);
+	let atlas = TextureAtlas::from_grid(handle, frame, def.sprite.columns, def.sprite.rows);
+	match old {
+		Some(old) => texture_atlases.set(old, atlas),
+		None => texture_atlases.add(atlas),
+	}
+}
+
+// Adds enemy types to the roster as their files load, swaps them out when a file is edited and
+// drops them when it's deleted
+fn update_roster(
+	mut events: EventReader<AssetEvent<EnemyDef>>,
+	defs: Res<Assets<EnemyDef>>,
+	asset_server: Res<AssetServer>,
+	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+	mut loading_assets: ResMut<LoadingAssets>,
+	mut roster: ResMut<EnemyRoster>,
+) {
+	for event in events.iter() {
+		let handle = match event {
+			AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+			AssetEvent::Removed { handle } => {
+				roster.0.retain(|_, kind| kind.source != handle.id);
+				continue;
+			}
+		};
+		let def = match defs.get(handle) {
+			Some(def) => def.clone(),
+			None => continue,
+		};
+		//the file may have moved to a different tile
+		let old_tile = roster.0.iter().find(|(_, kind)| kind.source == handle.id).map(|(tile, _)| *tile);
+		let old = old_tile.and_then(|tile| roster.0.remove(&tile));
+		let old_sprites = old.as_ref().map(|kind| &kind.sprites);
+		let sprites = EnemySprites {
+			normal: load_sheet(&def.sprite.sheet, &def, old_sprites.map(|sprites| &sprites.normal), &asset_server, &mut texture_atlases, &mut loading_assets),
+			hurt: def.sprite.hurt_sheet.as_ref().map(|path| {
+				let old_hurt = old_sprites.and_then(|sprites| sprites.hurt.as_ref());
+				load_sheet(path, &def, old_hurt, &asset_server, &mut texture_atlases, &mut loading_assets)
+			}),
+			frames: def.sprite.frames.max(1),
+		};
+		if let Some(other) = roster.0.get(&def.tile) {
+			warn!("enemies {:?} and {:?} both use tile {:?}, {:?} wins", other.def.name, def.name, def.tile, def.name);
+		}
+		info!("enemy {:?} on tile {:?}", def.name, def.tile);
+		roster.0.insert(def.tile, EnemyKind { def, sprites, source: handle.id });
+	}
+}
+
+// Spawns an enemy of the given kind, with its stats scaled for the room it's in
+pub fn spawn_enemy<'w, 's, 'a>(commands: &'a mut Commands<'w, 's>, kind: &EnemyKind, translation: Vec3, difficulty: &Difficulty) -> EntityCommands<'w, 's, 'a> {
+	let def = &kind.def;
+	let health = def.health * difficulty.enemy_health;
+	let mut enemy = commands.spawn_bundle(SpriteSheetBundle {
+		texture_atlas: kind.sprites.normal.clone(),
+		sprite: TextureAtlasSprite {
+			index: 0,
+			..default()
+		},
+		transform: Transform::from_translation(translation),
+		..default()
+	});
+	enemy
+		.insert(Health::from(health))
+		.insert(Enemy)
+		.insert(EnemyStats{
+			damage: def.contact_damage * difficulty.enemy_damage,
+			knockback: def.contact_knockback * TILE_SIZE,
+			speed: def.speed * difficulty.enemy_speed,
+		})
+		.insert(Brain::new(def.senses(), health))
+		.insert(kind.sprites.clone())
+		.insert(Loot(def.loot.clone()));
+	match def.behaviour {
+		Behaviour::Bat => {
+			enemy.insert(Bat::new(translation));
+		}
+		Behaviour::Groundhog => {
+			enemy.insert(Groundhog::new(health)).insert(NavAgent::new(false));
+		}
+	}
+	enemy
+}
+
+// Rolls the drops of every enemy with loot that was killed, where it was last seen
+fn drop_loot(
+	mut commands: Commands,
+	mut deaths: EventReader<DeathEvent>,
+	mut tracker: ResMut<LootTracker>,
+	bomb_sheet: Res<BombItemSheet>,
+	hp_sheet: Res<HealthItemSheet>,
+	loot_query: Query<(Entity, &Transform, &Loot)>,
+) {
+	let alive: HashMap<Entity, (Vec3, Vec<LootDef>)> = loot_query.iter()
+		.map(|(entity, transform, loot)| (entity, (transform.translation, loot.0.clone())))
+		.collect();
+	for death in deaths.iter() {
+		//depending on whether this ran before or after the killing hit, it's gone or not quite yet
+		let (translation, loot) = match alive.get(&death.target).or_else(|| tracker.0.get(&death.target)) {
+			Some(dead) => dead,
+			None => continue,
+		};
+		for drop in loot.iter().filter(|drop| random::<f32>() < drop.chance) {
+			spawn_pickup(&mut commands, drop.item, *translation, &bomb_sheet, &hp_sheet);
+		}
+	}
+	tracker.0 = alive;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn bundled_enemy_files_parse() {
+		let bat: EnemyDef = ron::de::from_str(include_str!("../assets/enemies/bat.enemy")).unwrap();
+		assert_eq!((bat.tile, bat.behaviour), ('E', Behaviour::Bat));
+		let groundhog: EnemyDef = ron::de::from_str(include_str!("../assets/enemies/groundhog.enemy")).unwrap();
+		assert_eq!((groundhog.tile, groundhog.behaviour), ('G', Behaviour::Groundhog));
+		assert!(groundhog.sprite.hurt_sheet.is_some());
+	}
+
+	#[test]
+	fn unknown_loot_is_rejected() {
+		let mut bat: EnemyDef = ron::de::from_str(include_str!("../assets/enemies/bat.enemy")).unwrap();
+		assert!(bat.check().is_ok());
+		bat.loot.push(LootDef { item: 'Z', chance: 0.5 });
+		assert!(bat.check().is_err());
+	}
+
+	#[test]
+	fn enemies_cant_use_level_tiles() {
+		let mut bat: EnemyDef = ron::de::from_str(include_str!("../assets/enemies/bat.enemy")).unwrap();
+		for tile in ['e', 'z'] {
+			bat.tile = tile;
+			assert!(bat.check().is_ok());
+		}
+		for tile in ['#', 'T', 'U', 'B', 'H', 'P', 'M', 'N', 'A', 'W', '-'] {
+			bat.tile = tile;
+			assert!(bat.check().is_err(), "{:?} was allowed", tile);
+		}
+	}
+}
@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+	TILE_SIZE,
+	ROOM_WIDTH,
+	ROOM_HEIGHT,
+	ANIM_TIME,
+	GameState,
+	level::{
+		Map,
+		ActiveRoom,
+		is_solid,
+		tile_at,
+		tile_center,
+	},
+	enemy::{
+		Enemy,
+		EnemyStats,
+	},
+	ai::{
+		AiState,
+		Brain,
+	},
+	enemy_def::EnemySprites,
+	nav::NavAgent,
+	player::{
+		Player,
+		Health,
+	},
+	combat::hit_stop_over,
+};
+
+const WALK_SPEED: f32 = 1.5 * TILE_SIZE; //per second, scaled by EnemyStats speed
+const DIG_SPEED: f32 = 2.5 * TILE_SIZE;
+const FALL_SPEED: f32 = 6. * TILE_SIZE;
+const SURFACE_RANGE: f32 = TILE_SIZE; //comes back up once this close to the player underground
+const BURROW_TIME: f32 = 3.; //seconds walking before it will dig down again
+const HURT_TIME: f32 = 0.4; //seconds the damage frames show after a hit
+
+// Groundhogs only tunnel through plain bricks, never traps or nests
+fn can_burrow(tile: char) -> bool {
+	tile == '#'
+}
+
+// Ground enemy: walks along the bricks, digs down into them when the player is out of reach and
+// pops back up next to them. Underground it isn't an Enemy, so it can't hurt or be hurt.
+#[derive(Component)]
+pub struct Groundhog {
+	burrowed: bool,
+	facing: f32, //-1 left, 1 right
+	burrow_timer: Timer,
+	hurt_timer: Timer,
+	anim_timer: Timer,
+	last_health: f32,
+}
+
+impl Groundhog {
+	pub fn new(health: f32) -> Self {
+		let mut hurt_timer = Timer::from_seconds(HURT_TIME, false);
+		hurt_timer.tick(std::time::Duration::from_secs_f32(HURT_TIME));
+		Self {
+			burrowed: false,
+			facing: 1.,
+			burrow_timer: Timer::from_seconds(BURROW_TIME, false),
+			hurt_timer,
+			anim_timer: Timer::from_seconds(ANIM_TIME, true),
+			last_health: health,
+		}
+	}
+}
+
+pub struct GroundhogPlugin;
+impl Plugin for GroundhogPlugin {
+	fn build (&self, app: &mut App) {
+		app.add_system(move_groundhogs.run_in_state(GameState::Playing).run_if(hit_stop_over))
+			.add_system(animate_groundhogs.run_in_state(GameState::Playing));
+	}
+}
+
+fn tile(room: &[[char; ROOM_WIDTH]; ROOM_HEIGHT], x: isize, y: isize) -> char {
+	if x < 0 || y < 0 || x >= ROOM_WIDTH as isize || y >= ROOM_HEIGHT as isize {
+		return 'U';
+	}
+	room[y as usize][x as usize]
+}
+
+fn move_groundhogs(
+	mut commands: Commands,
+	time: Res<Time>,
+	active_room: Option<Res<ActiveRoom>>,
+	map_query: Query<&Map>,
+	player_query: Query<&Transform, (With<Player>, Without<Groundhog>)>,
+	mut groundhog_query: Query<(Entity, &mut Groundhog, &mut Transform, &mut Visibility, &EnemyStats, &Brain, &mut NavAgent)>,
+) {
+	let (active_room, map) = match (active_room, map_query.get_single()) {
+		(Some(room), Ok(map)) => (room, map),
+		_ => return,
+	};
+	let room = match map.room(active_room.0, active_room.1) {
+		Some(room) => &room.room_coords,
+		None => return,
+	};
+	let player = player_query.get_single().map(|p| p.translation).ok();
+	let dt = time.delta_seconds();
+
+	for (entity, mut groundhog, mut transform, mut visibility, stats, brain, mut agent) in groundhog_query.iter_mut() {
+		agent.active = !groundhog.burrowed;
+		let (tx, ty) = match tile_at(transform.translation) {
+			Some((x, y)) => (x as isize, y as isize),
+			None => continue,
+		};
+		let center = tile_center(tx as usize, ty as usize);
+		let to_player = player.map(|p| p.x - transform.translation.x);
+		//fleeing is chasing in the other direction
+		let heading = match brain.state {
+			AiState::Chase | AiState::Attack => to_player,
+			AiState::Flee => to_player.map(|dx| -dx.signum() * 3. * TILE_SIZE),
+			_ => None,
+		};
+
+		if groundhog.burrowed {
+			//the brick it was in got mined out from around it
+			let surrounded = can_burrow(tile(room, tx, ty));
+			let close = heading.is_none_or(|dx| dx.abs() < SURFACE_RANGE);
+			if let Some(dx) = heading.filter(|_| !close) {
+				groundhog.facing = dx.signum();
+			}
+			//keeps going to the middle of its tile even if the next one can't be dug
+			let ahead = tile(room, tx + groundhog.facing as isize, ty);
+			let dig_on = !close && surrounded && (can_burrow(ahead) || (transform.translation.x - center.x) * groundhog.facing < 0.);
+
+			if dig_on {
+				transform.translation.x += groundhog.facing * DIG_SPEED * stats.speed * dt;
+			} else if !surrounded || !is_solid(tile(room, tx, ty - 1)) {
+				//pop out into the open tile above, or right here if the brick is gone
+				let up = if surrounded { 1 } else { 0 };
+				let surface = tile_center(tx as usize, (ty - up) as usize);
+				transform.translation.x = surface.x;
+				transform.translation.y = surface.y;
+				groundhog.burrowed = false;
+				groundhog.burrow_timer.reset();
+				visibility.is_visible = true;
+				commands.entity(entity).insert(Enemy);
+			}
+			continue;
+		}
+
+		//falls until it's standing on something
+		let floor = is_solid(tile(room, tx, ty + 1));
+		if !floor || transform.translation.y > center.y {
+			transform.translation.y -= FALL_SPEED * dt;
+			if floor {
+				transform.translation.y = transform.translation.y.max(center.y);
+			}
+			continue;
+		}
+
+		groundhog.burrow_timer.tick(time.delta());
+		//walks the nav path to the player when there is one, digs when there isn't
+		let waypoint = if brain.state == AiState::Flee { None } else { heading.and_then(|_| agent.next_waypoint(transform.translation)) };
+		if let Some(dx) = waypoint.map(|w| w.x - transform.translation.x).or(heading).or(to_player.filter(|_| brain.state == AiState::Alert)).filter(|dx| dx.abs() > 4.) {
+			groundhog.facing = dx.signum();
+		}
+		if matches!(brain.state, AiState::Idle | AiState::Alert) {
+			continue;
+		}
+
+		let next_x = transform.translation.x + groundhog.facing * WALK_SPEED * stats.speed * dt;
+		let ahead = tile_at(Vec3::new(next_x + groundhog.facing * TILE_SIZE / 2., center.y, 0.))
+			.map(|(x, _)| x as isize)
+			.unwrap_or(tx + groundhog.facing as isize);
+		let dropping = waypoint.is_some_and(|w| w.y < center.y - TILE_SIZE / 2.);
+		let blocked = ahead != tx && (is_solid(tile(room, ahead, ty)) || (!dropping && !is_solid(tile(room, ahead, ty + 1))));
+		let out_of_reach = waypoint.is_none() && heading.is_some_and(|dx| dx.abs() > 2. * TILE_SIZE);
+
+		if out_of_reach && groundhog.burrow_timer.finished() && can_burrow(tile(room, tx, ty + 1)) {
+			//digs into the brick it's standing on
+			let below = tile_center(tx as usize, (ty + 1) as usize);
+			transform.translation.x = below.x;
+			transform.translation.y = below.y;
+			groundhog.burrowed = true;
+			visibility.is_visible = false;
+			commands.entity(entity).remove::<Enemy>();
+		} else if blocked {
+			groundhog.facing = -groundhog.facing;
+		} else {
+			transform.translation.x = next_x;
+		}
+	}
+}
+
+fn animate_groundhogs(
+	time: Res<Time>,
+	mut groundhog_query: Query<(&mut Groundhog, &Health, &EnemySprites, &mut TextureAtlasSprite, &mut Handle<TextureAtlas>)>,
+) {
+	for (mut groundhog, health, sprites, mut sprite, mut sheet) in groundhog_query.iter_mut() {
+		if health.health < groundhog.last_health {
+			groundhog.hurt_timer.reset();
+		}
+		groundhog.last_health = health.health;
+		groundhog.hurt_timer.tick(time.delta());
+
+		let wanted = match &sprites.hurt {
+			Some(hurt) if !groundhog.hurt_timer.finished() => hurt,
+			_ => &sprites.normal,
+		};
+		if *sheet != *wanted {
+			*sheet = wanted.clone();
+			sprite.index = 0;
+		}
+		if groundhog.anim_timer.tick(time.delta()).just_finished() {
+			sprite.index = (sprite.index + 1) % sprites.frames;
+		}
+		sprite.flip_x = groundhog.facing < 0.;
+	}
+}
@@ -1,8 +1,7 @@
 use std::{
-    fs::File,
-    io::{BufRead, BufReader},
 	fmt,
-}; //might have to ask to use these
+	collections::{HashMap, HashSet},
+};
 
 
 use bevy::prelude::*;
@@ -13,8 +12,6 @@ use crate::{
 	WIN_W,
 	WIN_H,
 	TILE_SIZE,
-	MAP_WIDTH,
-	MAP_HEIGHT,
 	ROOM_WIDTH,
 	ROOM_HEIGHT,
 	GameState,
@@ -23,20 +20,72 @@ use crate::{
 		LoadingAssetInfo,
 	},
 	player::*,
-	enemy::*,
 	boss::*,
-	
+	difficulty::{
+		Difficulty,
+		danger,
+		difficulty_at,
+	},
+	room_file::{
+		RoomFile,
+		RoomFileLoader,
+		RoomTemplate,
+	},
+	biome::{
+		Biome,
+		BiomeInfo,
+		Lava,
+		LAVA_TINT,
+	},
+	water::{
+		WaterImage,
+		spawn_water_tile,
+	},
+	trap::{
+		TrapImage,
+		spawn_trap,
+	},
+	enemy_def::{
+		EnemyRoster,
+		spawn_enemy,
+	},
+	nav::NavAgent,
+	nest::spawn_nest,
+	pickaxe::{
+		Hardness,
+		PickaxeImage,
+		spawn_anvil,
+		spawn_pickaxe_pickup,
+	},
+	combat::{
+		Armour,
+		ScriptedDeath,
+	},
+	connectivity::{
+		neighbour,
+		opposite,
+		fit_room,
+		side_name,
+	},
 };
 
 const T: u32 = 5;	//CA threshold value
 const N: usize = 55;	//number of seed walls
 const P: u32 = 3;  //iterations of the CA to run
+const TEMPLATE_ODDS: u32 = 4; //1 in this many rooms that a template fits uses it instead of a cave
+const LAIR_DEPTH: (i32, i32) = (10, 14); //rows the boss lair can be placed on, inclusive
+const LAIR_SPREAD: i32 = 6; //furthest the lair can be placed left or right of the start room
+const LAIR_SALT: u64 = 3;
+const WANTED_SALT: u64 = 4;
+const NEST_SALT: u64 = 5;
+pub const POUND_SALT: u64 = 6;
+const EDGE_ODDS: u64 = 4; //1 in this many walls between rooms is open on top of the ones rooms ask for
 
 // room.exits indexes
-const LEFT: usize = 0;
-const RIGHT: usize = 1;
-const TOP: usize = 2;
-const BOTTOM: usize = 3;
+pub const LEFT: usize = 0;
+pub const RIGHT: usize = 1;
+pub const TOP: usize = 2;
+pub const BOTTOM: usize = 3;
 
 
 #[derive(Component)]
@@ -54,25 +103,60 @@ pub struct Door;
 #[derive(Component)]
 pub struct Unbreakable;
 
-#[derive(Component,Copy,Clone,Debug)]
+// Which tile of the current room an entity was spawned from, so we can tell when it's gone
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RoomTile {
+	pub x: usize,
+	pub y: usize,
+}
+
+// Map coords of the room that's currently spawned, set by setup_level
+// (enter_new_room moves the map coords before the old room is torn down)
+pub struct ActiveRoom(pub i32, pub i32);
+
+// Tiles that turn into open space once whatever they spawned is destroyed, killed or picked up
+// (a-z enemies from assets/enemies count too)
+const PERSISTED_TILES: [char; 9] = ['#', 'E', 'T', 'B', 'H', 'A', 'G', 'N', 'P'];
+
+#[derive(Component,Copy,Clone,Debug,PartialEq,Eq)]
 pub struct Room
 {
 	seed_wall_locations: [usize;N],
-	room_coords:[[char;ROOM_WIDTH]; ROOM_HEIGHT], //array of tiles in the room
-	exits: [bool;4],
+	pub room_coords:[[char;ROOM_WIDTH]; ROOM_HEIGHT], //array of tiles in the room
+	pub exits: [bool;4],
 }
 
 impl Room
 {
 	pub fn new(exits: [bool;4]) -> Self {
 		Self {
-			seed_wall_locations: gen_seed_wall_locations(),
+			seed_wall_locations: [0;N],
 			room_coords: [['-'; ROOM_WIDTH]; ROOM_HEIGHT],
 			exits,
 		}
 	}
 }
 
+// world position of the middle of a room tile, laid out from the top-left like setup_level does
+pub fn tile_center(x: usize, y: usize) -> Vec2 {
+	Vec2::new(-WIN_W/2. + TILE_SIZE/2. + x as f32 * TILE_SIZE, WIN_H/2. - TILE_SIZE/2. - y as f32 * TILE_SIZE)
+}
+
+// room tile a world position falls in, None outside the room
+pub fn tile_at(pos: Vec3) -> Option<(usize, usize)> {
+	let x = ((pos.x + WIN_W/2.) / TILE_SIZE).floor();
+	let y = ((WIN_H/2. - pos.y) / TILE_SIZE).floor();
+	if x < 0. || y < 0. || x >= ROOM_WIDTH as f32 || y >= ROOM_HEIGHT as f32 {
+		return None;
+	}
+	Some((x as usize, y as usize))
+}
+
+// tiles the player can't pass through, everything else counts as open space
+pub fn is_solid(tile: char) -> bool {
+	tile == '#' || tile == 'U' || tile == 'A' || tile == 'N'
+}
+
 impl fmt::Display for Room {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		let coords = &self.room_coords;
@@ -84,40 +168,291 @@ impl fmt::Display for Room {
 	}
 }
 
-#[derive(Component,Clone)]
+// Rooms are only generated the first time they're needed, so the map has no edges
+// The start room sits at (0, 0), x grows to the right and y grows downwards
+#[derive(Component,Clone,PartialEq)]
 pub struct Map
 {
-	map_coords: Vec<[Room;MAP_WIDTH]>, //array of rooms on the map
-	pub x_coords: usize,
-	pub y_coords: usize, //coordinates for location of the current room
+	seed: u64,
+	rooms: HashMap<(i32, i32), Room>, //every room generated so far
+	templates: Vec<RoomTemplate>, //handcrafted rooms mixed in with the generated caves
+	lair_route: Vec<(i32, i32)>, //rooms from the start down to the boss lair, the last one is the lair
+	pub x_coords: i32,
+	pub y_coords: i32, //coordinates for location of the current room
 	pub player_spawn: Transform,
 }
 
 impl Map
 {
-	pub fn new() -> Self {
-		Self{map_coords: vec![[Room::new([true, true, true, true]); MAP_WIDTH]; MAP_HEIGHT], x_coords: 0, y_coords: 0, player_spawn: Transform::from_xyz(-400., -(WIN_H/2.) + (TILE_SIZE * 2.5), 900.) }
+	pub fn new(seed: u64, templates: Vec<RoomTemplate>) -> Self {
+		let start = templates.iter()
+			.find(|t| t.has_tag("start"))
+			.map_or(Room::new([true; 4]), |t| t.room);
+		Self{
+			seed,
+			rooms: HashMap::from([((0, 0), start)]),
+			templates,
+			lair_route: lair_route(seed),
+			x_coords: 0,
+			y_coords: 0,
+			player_spawn: Transform::from_xyz(-400., -(WIN_H/2.) + (TILE_SIZE * 2.5), 900.),
+		}
+	}
+
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+
+	pub fn room(&self, x: i32, y: i32) -> Option<&Room> {
+		self.rooms.get(&(x, y))
+	}
+
+	pub fn room_mut(&mut self, x: i32, y: i32) -> Option<&mut Room> {
+		self.rooms.get_mut(&(x, y))
+	}
+
+	#[cfg(test)]
+	pub fn rooms(&self) -> impl Iterator<Item = (&(i32, i32), &Room)> {
+		self.rooms.iter()
+	}
+
+	// The room the player is in, generating it first if this is the first visit
+	pub fn current_room(&mut self) -> &Room {
+		self.room_or_generate(self.x_coords, self.y_coords)
+	}
+
+	// Where the boss lair is
+	pub fn lair(&self) -> (i32, i32) {
+		*self.lair_route.last().unwrap()
+	}
+
+	// Band the room the player is in belongs to
+	pub fn biome(&self) -> Biome {
+		Biome::at_depth(self.y_coords)
+	}
+
+	pub fn room_or_generate(&mut self, x: i32, y: i32) -> &Room {
+		if !self.rooms.contains_key(&(x, y)) {
+			let mut room = self.generate_room_at(x, y);
+			let report = fit_room(self, x, y, &mut room);
+			if !report.mismatched_sides.is_empty() {
+				let sides: Vec<&str> = report.mismatched_sides.iter().map(|side| side_name(*side)).collect();
+				warn!("room {:?} disagreed with its neighbours about its {:?} exits, changed them to match", [x, y], sides);
+			}
+			info!("generated room {:?} with exits {:?}, carved {} tiles", [x, y], room.exits, report.tiles_carved);
+			self.rooms.insert((x, y), room);
+		}
+		&self.rooms[&(x, y)]
+	}
+
+	// Exits come from edge(), so the room is the same whichever order the map gets explored in
+	fn generate_room_at(&self, x: i32, y: i32) -> Room {
+		if (x, y) == self.lair() {
+			info!("placed the boss lair at {:?}", [x, y]);
+			return self.templates.iter()
+				.find(|t| t.has_tag("lair"))
+				.map_or_else(fallback_lair, |t| t.room);
+		}
+		let mut rng = StdRng::seed_from_u64(mix_seed(self.seed, x, y, 0));
+		let mut exits = [false;4];
+		for side in [LEFT, RIGHT, TOP, BOTTOM] {
+			exits[side] = self.edge(x, y, side);
+		}
+		let required = exits.map(Some);
+		let fitting: Vec<&RoomTemplate> = self.templates.iter()
+			.filter(|t| !t.has_tag("start") && !t.has_tag("lair") && t.fits(&required, danger(x, y)))
+			.collect();
+		if !fitting.is_empty() && rng.gen_range(0..TEMPLATE_ODDS) == 0 {
+			let template = fitting[rng.gen_range(0..fitting.len())];
+			info!("placed room template {:?} at {:?}", template.tags, [x, y]);
+			return template.room;
+		}
+
+		generate_room(exits, &difficulty_at(x, y), Biome::at_depth(y).info(), &mut rng)
+	}
+
+	// Walls along the lair route are always open, and the lair is only open to the route
+	fn on_lair_route(&self, x: i32, y: i32, side: usize) -> bool {
+		let next = neighbour(x, y, side);
+		self.lair_route.windows(2).any(|step| step == [(x, y), next] || step == [next, (x, y)])
+	}
+
+	fn edge_forced(&self, x: i32, y: i32, side: usize) -> bool {
+		let lair = self.lair();
+		(x, y) == lair || neighbour(x, y, side) == lair || self.on_lair_route(x, y, side)
+	}
+
+	// Walls of the start room are whatever its template says
+	fn start_edge(&self, x: i32, y: i32, side: usize) -> Option<bool> {
+		let start = self.room(0, 0)?;
+		if (x, y) == (0, 0) {
+			Some(start.exits[side])
+		} else if neighbour(x, y, side) == (0, 0) {
+			Some(start.exits[opposite(side)])
+		} else {
+			None
+		}
+	}
+
+	// Whether the wall on `side` of a room is open. Only the seed and where the wall is go into it,
+	// and both rooms either side of it get the same answer, so nothing depends on which is generated first
+	pub fn edge(&self, x: i32, y: i32, side: usize) -> bool {
+		if let Some(open) = self.start_edge(x, y, side) {
+			return open;
+		}
+		if self.edge_forced(x, y, side) {
+			return self.on_lair_route(x, y, side);
+		}
+		let (nx, ny) = neighbour(x, y, side);
+		edge_open(self.seed, x, y, side)
+			|| self.wanted_sides(x, y).contains(&side)
+			|| self.wanted_sides(nx, ny).contains(&opposite(side))
+	}
+
+	// Two sides every room gets open so it's never a dead end, picked from the ones the start room
+	// and the lair route leave free
+	fn wanted_sides(&self, x: i32, y: i32) -> Vec<usize> {
+		let mut rng = StdRng::seed_from_u64(mix_seed(self.seed, x, y, WANTED_SALT));
+		let mut free: Vec<usize> = [LEFT, RIGHT, TOP, BOTTOM].into_iter()
+			.filter(|side| self.start_edge(x, y, *side).is_none() && !self.edge_forced(x, y, *side))
+			.collect();
+		free.shuffle(&mut rng);
+		free.truncate(2);
+		free
+	}
+}
+
+// Picks where the lair goes from the seed, then wanders down to it from the start room,
+// stepping sideways now and then so the way there isn't one straight shaft
+fn lair_route(seed: u64) -> Vec<(i32, i32)> {
+	let mut rng = StdRng::seed_from_u64(mix_seed(seed, 0, 0, LAIR_SALT));
+	let lair = (rng.gen_range(-LAIR_SPREAD..=LAIR_SPREAD), rng.gen_range(LAIR_DEPTH.0..=LAIR_DEPTH.1));
+	let mut route = vec![(0, 0)];
+	let (mut x, mut y) = (0, 0);
+	//only ever comes at the lair from above, so the rest of its walls can stay shut
+	while (x, y) != (lair.0, lair.1 - 1) {
+		if y < lair.1 - 1 && (x == lair.0 || rng.gen_bool(0.6)) {
+			y += 1;
+		} else {
+			x += (lair.0 - x).signum();
+		}
+		route.push((x, y));
+	}
+	route.push(lair);
+	route
+}
+
+// Bare arena, in case lair.room didn't load
+fn fallback_lair() -> Room {
+	let mut room = Room::new([false, false, true, false]);
+	for (i, row) in room.room_coords.iter_mut().enumerate() {
+		for (j, tile) in row.iter_mut().enumerate() {
+			if i == ROOM_HEIGHT - 1 || ((j == 0 || j == ROOM_WIDTH - 1) && i > 0) {
+				*tile = 'U';
+			}
+		}
+	}
+	room.room_coords[ROOM_HEIGHT - 2][ROOM_WIDTH / 2] = 'T';
+	room
+}
+
+// splitmix64 over the seed and a position, gives each room and edge its own stream
+pub fn mix_seed(seed: u64, x: i32, y: i32, salt: u64) -> u64 {
+	let mut z = seed
+		^ (x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+		^ (y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+		^ salt.wrapping_mul(0x1656_67B1_9E37_79F9);
+	z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+	z ^ (z >> 31)
+}
+
+// 1 in EDGE_ODDS chance for the wall between two rooms, both rooms see the same answer
+fn edge_open(seed: u64, x: i32, y: i32, side: usize) -> bool {
+	let (ex, ey, salt) = match side {
+		LEFT => (x - 1, y, 1),
+		RIGHT => (x, y, 1),
+		TOP => (x, y - 1, 2),
+		_ => (x, y, 2),
+	};
+	mix_seed(seed, ex, ey, salt).is_multiple_of(EDGE_ODDS)
+}
+
+// Seed for the map RNG, so a reported cave layout can be rebuilt exactly
+// Pass `--seed <n>` on the command line or type one in on the main menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapSeed(pub u64);
+
+impl MapSeed {
+	pub fn from_args() -> Self {
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let value = if arg == "--seed" {
+				let value = args.next();
+				if value.is_none() {
+					warn!("--seed needs a number after it, using a random seed");
+				}
+				value
+			} else {
+				arg.strip_prefix("--seed=").map(String::from)
+			};
+			if let Some(value) = value {
+				match value.parse() {
+					Ok(seed) => return Self(seed),
+					Err(_) => warn!("--seed {:?} isn't a whole number from 0 to {}, using a random seed", value, u64::MAX),
+				}
+			}
+		}
+		Self(random())
 	}
 }
+
 #[derive(Component)]
 pub struct BombItem;
 
 #[derive(Component)]
 pub struct HealthItem;
 
+// Every room file the generator pulls templates from, the start room included
+pub struct RoomFiles(Vec<Handle<RoomFile>>);
+
 // Will need to access these with .0, not deriving Deref/DerefMut
 pub struct BackgroundImage(Handle<Image>);
 pub struct DoorImage(Handle<Image>);
-pub struct BrickSheet(Handle<TextureAtlas>);
+pub struct BrickSheet(pub Handle<TextureAtlas>);
 pub struct BombItemSheet(Handle<TextureAtlas>);
 pub struct HealthItemSheet(Handle<TextureAtlas>);
 
+// Bomb ('B') or heart ('H') pickup, from a room tile or dropped by an enemy
+pub fn spawn_pickup(commands: &mut Commands, item: char, translation: Vec3, bomb_sheet: &BombItemSheet, hp_sheet: &HealthItemSheet) -> Entity {
+	let sheet = if item == 'B' { bomb_sheet.0.clone() } else { hp_sheet.0.clone() };
+	let mut pickup = commands.spawn_bundle(SpriteSheetBundle {
+		texture_atlas: sheet,
+		sprite: TextureAtlasSprite {
+			index: 0,
+			..default()
+		},
+		transform: Transform::from_translation(translation),
+		..default()
+	});
+	if item == 'B' {
+		pickup.insert(BombItem);
+	} else {
+		pickup.insert(HealthItem);
+	}
+	pickup.id()
+}
+
 pub struct LevelPlugin;
 impl Plugin for LevelPlugin {
 	fn build (&self, app: &mut App) {
-		app.add_enter_system(GameState::Loading, load_level)
-			.add_enter_system(GameState::Loading, generate_map)
+		app.insert_resource(MapSeed::from_args())
+			.add_asset::<RoomFile>()
+			.init_asset_loader::<RoomFileLoader>()
+			.add_enter_system(GameState::Loading, load_level)
+			.add_exit_system(GameState::MainMenu, generate_map)
 			.add_enter_system(GameState::Playing, setup_level)
+			.add_system(persist_room_changes.run_in_state(GameState::Playing))
 			.add_enter_system(GameState::Traverse,despawn_all)
 			;
 	}
@@ -182,23 +517,49 @@ fn load_level(
 	let hp_atlas_handle = texture_atlases.add(hp_atlas);
 
 	commands.insert_resource(HealthItemSheet(hp_atlas_handle));
+
+	//Room templates
+	let room_handles: Vec<Handle<RoomFile>> = ["rooms/start.room", "rooms/set_pieces.room", "rooms/lair.room"]
+		.into_iter()
+		.map(|path| asset_server.load(path))
+		.collect();
+	for handle in room_handles.iter() {
+		loading_assets.0.insert(
+			handle.clone_untyped(),
+			LoadingAssetInfo::for_handle(handle.clone_untyped(), &asset_server),
+		);
+	}
+	commands.insert_resource(RoomFiles(room_handles));
 }
 
 fn setup_level(
 	mut commands: Commands,
 	mut map_query: Query<&mut Map>,
-	texture_atlases: Res<Assets<TextureAtlas>>,	
 	background_image: Res<BackgroundImage>,
 	door_image: Res<DoorImage>,
 	brick_sheet: Res<BrickSheet>,
-	enemy_sheet: Res<EnemySheet>,
 	boss_sheet: Res<BossSheet>,
 	bomb_sheet: Res<BombItemSheet>,
-	hp_sheet: Res<HealthItemSheet>
+	hp_sheet: Res<HealthItemSheet>,
+	water_image: Res<WaterImage>,
+	trap_image: Res<TrapImage>,
+	pickaxe_image: Res<PickaxeImage>,
+	roster: Res<EnemyRoster>,
 ) {
+	let mut map = map_query.single_mut();
+	
+	//generates the room the first time it's entered
+	let current_room = *map.current_room();
+	let difficulty = difficulty_at(map.x_coords, map.y_coords);
+	let biome = map.biome().info();
+
 	commands
 		.spawn_bundle(SpriteBundle {
 			texture: background_image.0.clone(),
+			sprite: Sprite {
+				color: biome.background_tint,
+				..default()
+			},
 			transform: Transform {
 				translation: Vec3::new(0., 0. , 100.0), 
 				..default()
@@ -207,23 +568,10 @@ fn setup_level(
 		})
 		.insert(Background); //spawns background
 
-
-	//let file = File::open("assets/map.txt").expect("No map file found");
-	let brick_atlas = texture_atlases.get(&brick_sheet.0);
-	let brick_len = brick_atlas.unwrap().len();
-	let map = map_query.single_mut();
-	
-	//generate and store new room if OOB
-	/* 
-	let (x,y) = (map.x_coords,map.y_coords);
-
-	if map.map_coords.len() >= x && map.map_coords[x].len() >= y{
-		map.map_coords[x][y] = generate_room(map.cur_exits);
-	}
-	*/
-	let current_room = map.map_coords[map.y_coords][map.x_coords];
+	commands.insert_resource(ActiveRoom(map.x_coords, map.y_coords));
 	info!("{:?}", [map.x_coords, map.y_coords]);
 	info!("{:?}", current_room.exits);
+	info!("{} band", biome.name);
 	
 	let mut i = 0;
 	let t = Vec3::new(-WIN_W/2. + TILE_SIZE/2., WIN_H/2. - TILE_SIZE/2., 0.);
@@ -235,7 +583,8 @@ fn setup_level(
 						.spawn_bundle(SpriteSheetBundle {
 							texture_atlas: brick_sheet.0.clone(),
 							sprite: TextureAtlasSprite {
-								index: i % brick_len,
+								index: biome.tile_frames[i % biome.tile_frames.len()],
+								color: biome.tile_tint,
 								..default()
 							},
 							transform: Transform {
@@ -244,9 +593,11 @@ fn setup_level(
 							},
 							..default()
 						})
-						.insert(Health::new())
+						.insert(Health::from(100. * difficulty.brick_health * biome.brick_hardness))
+						.insert(Hardness(biome.brick_hardness))
 						.insert(Brick)
-						.insert(Collider);
+						.insert(Collider)
+						.insert(RoomTile{x, y});
 						i += 1;
 				},
 				'D'=> {
@@ -262,25 +613,8 @@ fn setup_level(
 						.insert(Door);
 						i += 1;
 				}
-				'E'=> {
-					commands
-						.spawn_bundle(SpriteSheetBundle {
-							texture_atlas: enemy_sheet.clone(),
-							sprite: TextureAtlasSprite {
-								index: 0,
-								..default()
-							},
-							transform: Transform {
-								translation: t + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.0),
-								..default()
-							},
-							..default()
-						})
-						.insert(Health::new())
-						.insert(Enemy);
-					i += 1;
-				}
 				'T'=> {
+					let health = BOSS_HEALTH * difficulty.enemy_health;
 					commands
 						.spawn_bundle(SpriteSheetBundle {
 							texture_atlas: boss_sheet.clone(),
@@ -294,8 +628,12 @@ fn setup_level(
 							},
 							..default()
 						})
-						.insert(Health::new())
-						.insert(Boss{health:100.0,y_velocity:0.0,y_accel:0.0,x_velocity:0.0,last_move: 0.0,turtled:false,path: Vec3::new(0.,0.,0.)});
+						.insert(Health::from(health))
+						.insert(Armour(1.))
+						.insert(ScriptedDeath)
+						.insert(Boss::new(health))
+						.insert(NavAgent::new(true))
+						.insert(RoomTile{x, y});
 					i += 1;
 				}
 				'U'=> {
@@ -303,7 +641,8 @@ fn setup_level(
 						.spawn_bundle(SpriteSheetBundle {
 							texture_atlas: brick_sheet.0.clone(),
 							sprite: TextureAtlasSprite {
-								index: i % brick_len,
+								index: biome.tile_frames[i % biome.tile_frames.len()],
+								color: biome.tile_tint,
 								..default()
 							},
 							transform: Transform {
@@ -317,195 +656,129 @@ fn setup_level(
 						.insert(Unbreakable);
 						i += 1;
 				}
-				'B'=> {
-					commands
-					.spawn_bundle(SpriteSheetBundle {
-						texture_atlas: bomb_sheet.0.clone(),
-						sprite: TextureAtlasSprite {
-							index: 0,
-							..default()
-						},
-						//transform: Transform::from_xyz(200., -(WIN_H/2.) + (TILE_SIZE * 1.22), 900.),
-						transform: Transform {
-								translation: t + Vec3::new(x as f32 * TILE_SIZE, (-(y as f32) * TILE_SIZE)-23.0, 900.0),
-								..default()
-							},
-						..default()
-					})
-					//.insert(AnimationTimer(Timer::from_seconds(ANIM_TIME, true)))
-					//.insert(Velocity::new())
-					.insert(BombItem);
-					//ENEMY CODE
+				'B' | 'H'=> {
+					let translation = t + Vec3::new(x as f32 * TILE_SIZE, (-(y as f32) * TILE_SIZE)-23.0, 900.0);
+					let item = spawn_pickup(&mut commands, *char, translation, &bomb_sheet, &hp_sheet);
+					commands.entity(item).insert(RoomTile{x, y});
+					i += 1;
+				}
+				'P'=> {
+					let translation = t + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 900.0);
+					spawn_pickaxe_pickup(&mut commands, &pickaxe_image, translation, RoomTile{x, y});
 					i += 1;
 				}
-				'H'=> {
+				'M'=> {
+					let translation = t + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 800.0);
+					spawn_anvil(&mut commands, &pickaxe_image, translation);
+					i += 1;
+				}
+				'L'=> {
 					commands
-					.spawn_bundle(SpriteSheetBundle {
-						texture_atlas: hp_sheet.0.clone(),
-						sprite: TextureAtlasSprite {
-							index: 0,
-							..default()
-						},
-						//transform: Transform::from_xyz(200., -(WIN_H/2.) + (TILE_SIZE * 1.22), 900.),
-						transform: Transform {
-								translation: t + Vec3::new(x as f32 * TILE_SIZE, (-(y as f32) * TILE_SIZE)-23.0, 900.0),
+						.spawn_bundle(SpriteSheetBundle {
+							texture_atlas: brick_sheet.0.clone(),
+							sprite: TextureAtlasSprite {
+								index: 0,
+								color: LAVA_TINT,
 								..default()
 							},
-						..default()
-					})
-					//.insert(AnimationTimer(Timer::from_seconds(ANIM_TIME, true)))
-					//.insert(Velocity::new())
-					.insert(HealthItem);
-					//ENEMY CODE
+							transform: Transform {
+								translation: t + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.0),
+								..default()
+							},
+							..default()
+						})
+						.insert(Lava);
 					i += 1;
 				}
-				_=> {
-					
-					//default case
-				}
-			}
-		}
-    }
-}
-
-fn generate_map(
-	mut commands: Commands,
-	//mut rooms_query: Query<(&mut GennedRooms)>,
-	) {
-		let mut new_map = Map::new(); 
-		let mut rand_exits : [bool;4] = [true;4];
-		let mut rng = rand::thread_rng();
-		for i in 0..MAP_HEIGHT /*in new_map.map_coords.iter_mut().enumerate()*/ {
-			for j in 0..MAP_WIDTH/* in row.iter_mut().enumerate()*/ {
-				
-				let r1 : u8 = rng.gen_range(0..=1);
-				if r1 == 0 {
-					rand_exits[BOTTOM] = false;
-				}
-				else {
-					rand_exits[BOTTOM] = true;
-				}
-				let r2 : u8 = rng.gen_range(0..=1);
-				if r2 == 0 {
-					rand_exits[RIGHT] = false; 
-				}
-				else {
-					rand_exits[RIGHT] = true;
-				} //random assignments need to be at the top of the function in case we need to reassign them
-				
-				// we only randomly generate our bottom and right exits, since we know what the top and left have to be based on the previously generated rooms
-				
-				if i == (MAP_HEIGHT-1)/2 {
-					if j == ((MAP_WIDTH-1)/2) - 1 {
-						rand_exits[RIGHT] = true;
-					 //connect to left exit of center room (we don't need to do the right exit because we check for that anyway)
-					}
-					else if j == ((MAP_WIDTH-1)/2) {
-						//code to load in this room
-						new_map.map_coords[i][j] = starting_room();
-						continue; //SKIP GENERATING THIS ROOM so we can use a starting room that is not random
-					}
+				'W'=> {
+					spawn_water_tile(&mut commands, &water_image, x, y);
+					i += 1;
 				}
-				if j == (MAP_WIDTH-1)/2 && i == ((MAP_HEIGHT-1)/2) - 1 {
-    					rand_exits[BOTTOM] = true;
-    					//connect to top exit of center room (we don't need to do the bottom because we check for that later anyway.)
-    					}
-				//above section ensures we can insert a room in the middle and leave it connected
-				
-				if i>0 && new_map.map_coords[i-1][j].exits[BOTTOM] { //is the above room connected to this one
-					rand_exits[TOP] = true;
+				'A'=> {
+					let translation = t + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.0);
+					let trap = spawn_trap(&mut commands, &trap_image, translation, RoomTile{x, y}, difficulty.brick_health * biome.brick_hardness);
+					commands.entity(trap).insert(Hardness(biome.brick_hardness));
+					i += 1;
 				}
-				else {
-					rand_exits[TOP] = false;			
+				'N'=> {
+					let translation = t + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.0);
+					//enemies come out of whichever side of it is open, the top if it can
+					let exit = [(0, -1), (-1, 0), (1, 0), (0, 1)].iter()
+						.map(|(dx, dy)| (x as isize + dx, y as isize + dy))
+						.find(|(nx, ny)| *nx >= 0 && *ny >= 0 && (*nx as usize) < ROOM_WIDTH && (*ny as usize) < ROOM_HEIGHT && !is_solid(current_room.room_coords[*ny as usize][*nx as usize]))
+						.map_or(translation, |(nx, ny)| tile_center(nx as usize, ny as usize).extend(800.));
+					//same pick every time the room is built, from the nest's spot in the whole map
+					let (world_x, world_y) = (map.x_coords * ROOM_WIDTH as i32 + x as i32, map.y_coords * ROOM_HEIGHT as i32 + y as i32);
+					let spawns = biome.pick_enemy(&mut StdRng::seed_from_u64(mix_seed(map.seed, world_x, world_y, NEST_SALT)));
+					let nest = spawn_nest(&mut commands, brick_sheet.0.clone(), translation, exit, spawns, RoomTile{x, y}, difficulty.brick_health * biome.brick_hardness);
+					commands.entity(nest).insert(Hardness(biome.brick_hardness));
+					i += 1;
 				}
-				if j>0 && new_map.map_coords[i][j-1].exits[RIGHT] { //is the left room connected to this one
-					rand_exits[LEFT] = true;
+				//enemies, after every built in tile so a definition can't take one over
+				tile if roster.get(*tile).is_some() => {
+					let translation = t + Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 800.0);
+					spawn_enemy(&mut commands, roster.get(*tile).unwrap(), translation, &difficulty)
+						.insert(RoomTile{x, y});
+					i += 1;
 				}
-				else {
-					rand_exits[LEFT] = false;
+				//enemy tiles whose definition didn't load, or hasn't yet
+				'E' | 'G' => {
+					warn!("no enemy definition for tile {:?}, check assets/enemies", char);
 				}
-				if i == MAP_HEIGHT-1 {
-					rand_exits[BOTTOM] = false;
+				tile if tile.is_ascii_lowercase() => {
+					warn!("no enemy definition for tile {:?}", tile);
 				}
-				if j == MAP_WIDTH-1 {
-					rand_exits[RIGHT] = false;
+				_=> {
+					
+					//default case
 				}
-				//info!("{}", i);
-				//info!("{}", j);
-				info!("{:?}", [i,j]);
-				if i>0 { info!("{:?}", [rand_exits[TOP],new_map.map_coords[i-1][j].exits[BOTTOM]]);}
-				new_map.map_coords[i][j] = generate_room(rand_exits);
 			}
-			
 		}
-		new_map.x_coords = (MAP_WIDTH-1)/2;
-		new_map.y_coords = (MAP_HEIGHT-1)/2;
-		commands.spawn().insert(new_map);
+    }
 }
 
-fn starting_room() -> Room {
-	let file = File::open("assets/start_room.txt").expect("No map file found");
-	let mut new_room = Room::new([true; 4]);
-	for(x, line) in BufReader::new(file).lines().enumerate() { //read each line from file
-		if let Ok(line) = line {
-			for (y, char) in line.chars().enumerate() { //read each char from line
-				new_room.room_coords[x%9][y%16] = char;
+// Writes mined bricks, dead enemies and collected items back into the map so the room stays that way
+fn persist_room_changes(
+	active_room: Option<Res<ActiveRoom>>,
+	mut map_query: Query<&mut Map>,
+	tiles: Query<&RoomTile>,
+) {
+	let active_room = match active_room {
+		Some(room) => room,
+		None => return,
+	};
+	let mut map = match map_query.get_single_mut() {
+		Ok(map) => map,
+		Err(_) => return,
+	};
+	let alive: HashSet<RoomTile> = tiles.iter().copied().collect();
+	let room = match map.room_mut(active_room.0, active_room.1) {
+		Some(room) => room,
+		None => return,
+	};
+	for (y, row) in room.room_coords.iter_mut().enumerate() {
+		for (x, tile) in row.iter_mut().enumerate() {
+			if (PERSISTED_TILES.contains(tile) || tile.is_ascii_lowercase()) && !alive.contains(&RoomTile{x, y}) {
+				*tile = '-';
 			}
 		}
 	}
-	new_room
 }
 
-
-fn read_map( //No longer used
+fn generate_map(
 	mut commands: Commands,
-	
-	//texture_atlases: Res<Assets<TextureAtlas>>,	
-	//background_image: Res<BackgroundImage>,
-	//door_image: Res<DoorImage>,
-	//brick_sheet: Res<BrickSheet>,
-	//enemy_sheet: Res<EnemySheet>,
-) {
-	let file = File::open("assets/map.txt").expect("No map file found");
-	//let brick_atlas = texture_atlases.get(&brick_sheet.0);
-	//let brick_len = brick_atlas.unwrap().len();
-	let mut map = Map::new();
-	let mut z1 = 0;
-	let mut z2 = 0; //Current Map location is [z1],[z2]
-	let mut current_room = map.map_coords[z1][z2];
-	let _t = Vec3::new(-WIN_W/2. + TILE_SIZE/2., WIN_H/2. - TILE_SIZE/2., 0.);
-	for(x, line) in BufReader::new(file).lines().enumerate() { //read each line from file
-		if let Ok(line) = line {
-			for (y, char) in line.chars().enumerate() { //read each char from line
-				match char { 
-					
-					'!'=> { //End of Room
-						map.map_coords[z1][z2] = current_room;
-						if z2<MAP_WIDTH-1 { //counting starts at 0
-						z2+= 1;
-						}
-						else if z1<MAP_HEIGHT-1 {
-							z2 = 0;
-							z1 +=1;
-						}
-						current_room = map.map_coords[z1][z2];
-					}
-					
-					//needs case for directional exit markers
-					
-					_=> {
-						//info!("{}", char);
-						current_room.room_coords[x%9][y%16] = char;
-						//default case
-					}
-				}
-			}
-		}
-    }
-	map.map_coords[z1][z2] = current_room; //need to add the last room since the loop never does
-	//might add a line here to set the current room to the middle of the map
-	commands.spawn().insert(map);
-} 
+	seed: Res<MapSeed>,
+	room_files: Res<RoomFiles>,
+	room_assets: Res<Assets<RoomFile>>,
+	) {
+		//files that failed to parse were already reported by the asset server, they just add no templates
+		let templates: Vec<RoomTemplate> = room_files.0.iter()
+			.filter_map(|handle| room_assets.get(handle))
+			.flat_map(|file| file.0.iter().cloned())
+			.collect();
+		info!("Generating map with seed {} and {} room templates", seed.0, templates.len());
+		commands.spawn().insert(Map::new(seed.0, templates));
+}
 
 /*
 Divide Map File into "Cells" (9:16 Rooms)
@@ -519,10 +792,10 @@ This trait will be important for generation, so we can make sure that adjacent r
 
 */
 
-fn generate_room(exits: [bool;4]) -> Room {
+fn generate_room(exits: [bool;4], difficulty: &Difficulty, biome: &BiomeInfo, rng: &mut StdRng) -> Room {
 	let mut new_room = Room::new(exits);
+	new_room.seed_wall_locations = gen_seed_wall_locations(rng);
 	let mut cell_count = 0;
-	let mut rng = thread_rng();
 	let _door_here = rng.gen_range(0..100) == 50;
 
 	for (i, row) in new_room.room_coords.iter_mut().enumerate() {
@@ -532,22 +805,26 @@ fn generate_room(exits: [bool;4]) -> Room {
 				*character = 'U';
 			}
 
-			if *character == '-' && rng.gen_range(0..35) == 5 {
-				*character = 'E';
+			if *character == '-' && Difficulty::roll(difficulty.enemy_odds, rng) {
+				*character = biome.pick_enemy(rng);
 			}
 
-			if *character == '-' && rng.gen_range(0..30) == 10 {
+			if *character == '-' && Difficulty::roll(difficulty.bomb_odds, rng) {
 				*character = 'B';
 			}
 
-			if *character == '-' && rng.gen_range(0..100) == 3 {
+			if *character == '-' && Difficulty::roll(difficulty.door_odds, rng) {
 				*character = 'D';
 			}
 
-			if *character == '-' && rng.gen_range(0..50) == 5 {
+			if *character == '-' && Difficulty::roll(difficulty.heart_odds, rng) {
 				*character = 'H';
 			}
 
+			if *character == '-' && Difficulty::roll(difficulty.pickaxe_odds, rng) {
+				*character = 'P';
+			}
+
 			//place seed walls
 			cell_count += 1;
 			for location in new_room.seed_wall_locations {
@@ -591,11 +868,46 @@ fn generate_room(exits: [bool;4]) -> Room {
 			}
 		}
 	}
+	place_hazards(&mut new_room, biome, rng);
+	place_wall_tiles(&mut new_room, difficulty, rng);
 	new_room
 }
 
-fn gen_seed_wall_locations() -> [usize;N] {
-	let mut rng = thread_rng();
+// Turns some of the bricks facing open space into nests and arrow traps, they stay solid so paths don't change
+fn place_wall_tiles(room: &mut Room, difficulty: &Difficulty, rng: &mut StdRng) {
+	for i in 1..ROOM_HEIGHT-1 {
+		for j in 1..ROOM_WIDTH-1 {
+			let exposed = [(i-1, j), (i+1, j), (i, j-1), (i, j+1)].iter().any(|(ni, nj)| room.room_coords[*ni][*nj] == '-');
+			if room.room_coords[i][j] != '#' || !exposed {
+				continue;
+			}
+			if Difficulty::roll(difficulty.nest_odds, rng) {
+				room.room_coords[i][j] = 'N';
+			} else if Difficulty::roll(difficulty.trap_odds, rng) {
+				room.room_coords[i][j] = 'A';
+			}
+		}
+	}
+}
+
+// Biome hazards sit on open floor inside the room, never on the border so exits stay clear
+fn place_hazards(room: &mut Room, biome: &BiomeInfo, rng: &mut StdRng) {
+	for i in 1..ROOM_HEIGHT-1 {
+		for j in 1..ROOM_WIDTH-1 {
+			if room.room_coords[i][j] != '-' || !is_solid(room.room_coords[i+1][j]) {
+				continue;
+			}
+			for (tile, odds) in biome.hazards {
+				if Difficulty::roll(*odds, rng) {
+					room.room_coords[i][j] = *tile;
+					break;
+				}
+			}
+		}
+	}
+}
+
+fn gen_seed_wall_locations(rng: &mut StdRng) -> [usize;N] {
 	let mut arr: [usize;N] = [0;N];	
 	for num in arr.iter_mut() {
 		*num = rng.gen_range(0..ROOM_WIDTH*ROOM_HEIGHT);
@@ -619,3 +931,107 @@ fn despawn_all(
 }
 
 
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::room_file::bundled_templates;
+	use crate::connectivity::reachable_rooms;
+
+	// walks out from the start room through open exits, generating rooms in the order a player would find them
+	fn explore(map: &mut Map, limit: usize) -> Vec<(i32, i32)> {
+		let mut order = vec![(0, 0)];
+		let mut next = 0;
+		while next < order.len() && order.len() < limit {
+			let (x, y) = order[next];
+			let exits = map.room_or_generate(x, y).exits;
+			for side in [LEFT, RIGHT, TOP, BOTTOM] {
+				let n = neighbour(x, y, side);
+				if exits[side] && !order.contains(&n) {
+					map.room_or_generate(n.0, n.1);
+					order.push(n);
+				}
+			}
+			next += 1;
+		}
+		order
+	}
+
+	fn map_bytes(map: &Map, order: &[(i32, i32)]) -> Vec<u8> {
+		order.iter()
+			.filter_map(|(x, y)| map.room(*x, *y))
+			.flat_map(|room| format!("{}{:?}", room, room.exits).into_bytes())
+			.collect()
+	}
+
+	#[test]
+	fn same_seed_builds_identical_map() {
+		let (mut a, mut b) = (Map::new(1234, bundled_templates()), Map::new(1234, bundled_templates()));
+		let order = explore(&mut a, 200);
+		explore(&mut b, 200);
+		assert!(a == b);
+		assert_eq!(map_bytes(&a, &order), map_bytes(&b, &order));
+	}
+
+	#[test]
+	fn different_seeds_build_different_maps() {
+		let (mut a, mut b) = (Map::new(1, bundled_templates()), Map::new(2, bundled_templates()));
+		let order = explore(&mut a, 50);
+		explore(&mut b, 50);
+		assert_ne!(map_bytes(&a, &order), map_bytes(&b, &order));
+	}
+
+	#[test]
+	fn explored_rooms_are_all_reachable() {
+		let mut map = Map::new(99, bundled_templates());
+		let order = explore(&mut map, 300);
+		let reachable = reachable_rooms(&map);
+		assert!(order.iter().all(|room| reachable.contains(room)));
+	}
+
+	#[test]
+	fn lair_is_deep_and_reachable() {
+		for seed in [3, 42, 9001] {
+			let mut map = Map::new(seed, bundled_templates());
+			let (lx, ly) = map.lair();
+			assert!(ly >= LAIR_DEPTH.0);
+			for (x, y) in map.lair_route.clone() {
+				map.room_or_generate(x, y);
+			}
+			assert!(reachable_rooms(&map).contains(&(lx, ly)));
+
+			//open only to the route above, whatever gets generated around it later
+			for side in [LEFT, RIGHT, BOTTOM] {
+				let (nx, ny) = neighbour(lx, ly, side);
+				assert!(!map.room_or_generate(nx, ny).exits[opposite(side)]);
+			}
+			let lair = map.room(lx, ly).unwrap();
+			assert_eq!(lair.exits, [false, false, true, false]);
+			assert!(lair.room_coords.iter().flatten().any(|tile| *tile == 'T'));
+		}
+	}
+
+	#[test]
+	fn rooms_are_the_same_in_any_order() {
+		let coords: Vec<(i32, i32)> = (-4..5).flat_map(|y| (-4..5).map(move |x| (x, y))).collect();
+		let (mut a, mut b) = (Map::new(555, bundled_templates()), Map::new(555, bundled_templates()));
+		for (x, y) in coords.iter() {
+			a.room_or_generate(*x, *y);
+		}
+		//outside in and mirrored, so rooms meet their neighbours from the other side
+		let mut shuffled = coords.clone();
+		shuffled.sort_by_key(|(x, y)| (-(x.abs() + y.abs()), -*x, *y));
+		for (x, y) in shuffled.iter() {
+			b.room_or_generate(*x, *y);
+		}
+		assert_eq!(map_bytes(&a, &coords), map_bytes(&b, &coords));
+	}
+
+	#[test]
+	fn map_grows_past_the_old_edges() {
+		let mut map = Map::new(7, bundled_templates());
+		map.room_or_generate(-150, 420);
+		map.room_or_generate(-151, 420);
+		assert_eq!(map.room(-150, 420).unwrap().exits[LEFT], map.room(-151, 420).unwrap().exits[RIGHT]);
+	}
+}
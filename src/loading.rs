@@ -121,10 +121,10 @@ fn update_loading(
 
 	progress_transform.scale.x = PROGRESS_LENGTH * percent;
 
-	// Check if all assets are loaded
-	if asset_server.get_group_load_state(loading_assets.keys().map(|h| h.id))
-		== LoadState::Loaded
-	{
+	// Check if all assets are done, failures were already warned about so don't hang on them
+	let done = loading_assets.values()
+		.all(|i| matches!(i.state, LoadState::Loaded | LoadState::Failed));
+	if done {
 		commands.insert_resource(NextState(GameState::MainMenu));
 	}
 }
@@ -1,4 +1,8 @@
+//systems ask for whatever queries and resources they need, so these are just noise here
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
 use bevy::{
+	asset::AssetServerSettings,
 	window::PresentMode,
 	prelude::*,	
 };
@@ -13,6 +17,21 @@ mod boss;
 mod enemy;
 mod start_menu;
 mod bomb_item;
+mod connectivity;
+mod room_file;
+mod difficulty;
+mod biome;
+mod water;
+mod trap;
+mod groundhog;
+mod ai;
+mod nav;
+mod enemy_def;
+mod nest;
+mod combat;
+mod boss_fight;
+mod controls;
+mod pickaxe;
 
 use loading::LoadingPlugin;
 use level::LevelPlugin;
@@ -21,6 +40,18 @@ use enemy::EnemyPlugin;
 use start_menu::MainMenuPlugin;
 use bomb_item::BombPlugin;
 use boss::BossPlugin;
+use biome::BiomePlugin;
+use water::WaterPlugin;
+use trap::TrapPlugin;
+use groundhog::GroundhogPlugin;
+use ai::AiPlugin;
+use nav::NavPlugin;
+use enemy_def::EnemyDefPlugin;
+use nest::NestPlugin;
+use combat::CombatPlugin;
+use boss_fight::BossFightPlugin;
+use controls::ControlsPlugin;
+use pickaxe::PickaxePlugin;
 
 const TITLE: &str = "Miner Pitfall!";
 const WIN_W: f32 = 1280.;
@@ -29,7 +60,6 @@ const PLAYER_SPEED: f32 = 6.;
 const TIME_STEP: f32 = 1. / 60.;
 const BASE_SPEED: f32 = 500.;
 const ANIM_TIME: f32 = 0.2;
-const JUMP_TIME: f32 = 120.;
 const INV_TIME: f32 = 1.;
 const FRAME_TIME: f32 = 0.016667;
 const TILE_SIZE: f32 = 80.;
@@ -39,8 +69,6 @@ const PROGRESS_FRAME: f32 = 5.;
 const PLAYER_BOLT_SPRITE: &str = "bolt.png";
 const PLAYER_BOLT_SIZE: (f32, f32) = (9., 54.);
 const SPRITE_SCALE: f32 = 0.5;
-const MAP_WIDTH: usize = 100;
-const MAP_HEIGHT: usize = 100;
 const ROOM_WIDTH: usize = 16;
 const ROOM_HEIGHT: usize = 9;
 
@@ -87,6 +115,11 @@ fn main() {
 			"landin-credits.png", "Grant-Credit.png", "trezza-credit.png"],
 			timer_start: true
 		})
+		//reloads enemy definitions and other assets when they're edited
+		.insert_resource(AssetServerSettings {
+			watch_for_changes: true,
+			..default()
+		})
 		.add_plugins(DefaultPlugins)
 		// Set initial state
 		.add_loopless_state(GameState::Loading)
@@ -110,6 +143,18 @@ fn main() {
 		.add_plugin(MainMenuPlugin)
 		.add_plugin(BombPlugin)
 		.add_plugin(BossPlugin)
+		.add_plugin(BiomePlugin)
+		.add_plugin(WaterPlugin)
+		.add_plugin(TrapPlugin)
+		.add_plugin(GroundhogPlugin)
+		.add_plugin(AiPlugin)
+		.add_plugin(NavPlugin)
+		.add_plugin(EnemyDefPlugin)
+		.add_plugin(NestPlugin)
+		.add_plugin(CombatPlugin)
+		.add_plugin(BossFightPlugin)
+		.add_plugin(ControlsPlugin)
+		.add_plugin(PickaxePlugin)
 		.run();
 }
 
@@ -0,0 +1,292 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+	ROOM_WIDTH,
+	ROOM_HEIGHT,
+	GameState,
+	level::{
+		Map,
+		ActiveRoom,
+		is_solid,
+		tile_at,
+		tile_center,
+	},
+	player::Player,
+};
+
+/*
+Navigation for things that walk. Every open tile with something solid under it is a node, and
+nodes are linked by:
+	walk   to the tile beside it
+	drop   off a ledge, straight down to wherever it lands
+	jump   up to JUMP_HEIGHT tiles and over up to JUMP_REACH tiles, if nothing is in the way
+The graph is rebuilt whenever the room grid changes, so mined bricks open new paths straight away.
+Anything solid that isn't in the grid, like the seals over the lair's exits, gets a NavBlocker so
+the graph treats its tile as a wall.
+*/
+
+const JUMP_HEIGHT: usize = 2;
+const JUMP_REACH: usize = 2;
+const REPATH_TIME: f32 = 0.5; //seconds between path updates
+
+pub type Tile = (usize, usize); //(x, y) in the room grid
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+	Walk,
+	Drop,
+	Jump,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Link {
+	pub to: Tile,
+	pub kind: LinkKind,
+	cost: u32,
+}
+
+// Solid thing that isn't part of the room grid
+#[derive(Component)]
+pub struct NavBlocker;
+
+#[derive(Default)]
+pub struct NavGrid {
+	grid: Option<[[char; ROOM_WIDTH]; ROOM_HEIGHT]>, //the room this was built from
+	links: HashMap<Tile, Vec<Link>>,
+}
+
+fn open(grid: &[[char; ROOM_WIDTH]; ROOM_HEIGHT], x: usize, y: usize) -> bool {
+	!is_solid(grid[y][x])
+}
+
+fn standable(grid: &[[char; ROOM_WIDTH]; ROOM_HEIGHT], x: usize, y: usize) -> bool {
+	open(grid, x, y) && y + 1 < ROOM_HEIGHT && is_solid(grid[y + 1][x])
+}
+
+impl NavGrid {
+	pub fn build(grid: &[[char; ROOM_WIDTH]; ROOM_HEIGHT]) -> Self {
+		let mut links: HashMap<Tile, Vec<Link>> = HashMap::new();
+		for y in 0..ROOM_HEIGHT {
+			for x in 0..ROOM_WIDTH {
+				if !standable(grid, x, y) {
+					continue;
+				}
+				let node = links.entry((x, y)).or_default();
+				for side in [-1isize, 1] {
+					let nx = x as isize + side;
+					if nx < 0 || nx >= ROOM_WIDTH as isize || !open(grid, nx as usize, y) {
+						continue;
+					}
+					let nx = nx as usize;
+					if standable(grid, nx, y) {
+						node.push(Link { to: (nx, y), kind: LinkKind::Walk, cost: 1 });
+					} else if let Some(land) = (y + 1..ROOM_HEIGHT).take_while(|ny| open(grid, nx, *ny)).find(|ny| standable(grid, nx, *ny)) {
+						node.push(Link { to: (nx, land), kind: LinkKind::Drop, cost: 1 + (land - y) as u32 / 2 });
+					}
+				}
+
+				//straight up out of this tile, then across at the top
+				let headroom = (1..=JUMP_HEIGHT).take_while(|up| *up <= y && open(grid, x, y - up)).count();
+				for up in 1..=headroom {
+					let top = y - up;
+					for side in [-1isize, 1] {
+						for reach in 1..=JUMP_REACH {
+							let nx = x as isize + side * reach as isize;
+							if nx < 0 || nx >= ROOM_WIDTH as isize || !open(grid, nx as usize, top) {
+								break;
+							}
+							if standable(grid, nx as usize, top) {
+								node.push(Link { to: (nx as usize, top), kind: LinkKind::Jump, cost: 2 + (up + reach) as u32 });
+							}
+						}
+					}
+				}
+			}
+		}
+		Self { grid: Some(*grid), links }
+	}
+
+	pub fn links(&self, tile: Tile) -> &[Link] {
+		self.links.get(&tile).map_or(&[], |l| l.as_slice())
+	}
+
+	// The node something at this tile is standing on or will land on
+	pub fn node_below(&self, (x, y): Tile) -> Option<Tile> {
+		(y..ROOM_HEIGHT).map(|ny| (x, ny)).take_while(|tile| self.grid.is_some_and(|g| open(&g, tile.0, tile.1))).find(|tile| self.links.contains_key(tile))
+	}
+
+	// A* from one node to another, jump links only if the walker can jump. The path leaves out `from`.
+	pub fn find_path(&self, from: Tile, to: Tile, can_jump: bool) -> Option<Vec<Tile>> {
+		let estimate = |t: Tile| (t.0 as i32 - to.0 as i32).unsigned_abs() + (t.1 as i32 - to.1 as i32).unsigned_abs();
+		let mut open_set = BinaryHeap::from([Step { cost: estimate(from), tile: from }]);
+		let mut came_from: HashMap<Tile, Tile> = HashMap::new();
+		let mut best: HashMap<Tile, u32> = HashMap::from([(from, 0)]);
+
+		while let Some(Step { tile, .. }) = open_set.pop() {
+			if tile == to {
+				let mut path = vec![tile];
+				while let Some(prev) = came_from.get(path.last().unwrap()) {
+					path.push(*prev);
+				}
+				path.pop();
+				path.reverse();
+				return Some(path);
+			}
+			let so_far = best[&tile];
+			for link in self.links(tile).iter().filter(|l| can_jump || l.kind != LinkKind::Jump) {
+				let cost = so_far + link.cost;
+				if best.get(&link.to).is_none_or(|b| cost < *b) {
+					best.insert(link.to, cost);
+					came_from.insert(link.to, tile);
+					open_set.push(Step { cost: cost + estimate(link.to), tile: link.to });
+				}
+			}
+		}
+		None
+	}
+}
+
+// min-heap entry for A*
+#[derive(PartialEq, Eq)]
+struct Step {
+	cost: u32,
+	tile: Tile,
+}
+
+impl Ord for Step {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.cost.cmp(&self.cost).then_with(|| self.tile.cmp(&other.tile))
+	}
+}
+
+impl PartialOrd for Step {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+// Anything that wants paths to the player
+#[derive(Component)]
+pub struct NavAgent {
+	pub path: Vec<Tile>,
+	pub can_jump: bool,
+	pub active: bool, //set false to stop planning, e.g. while underground
+	repath: Timer,
+}
+
+impl NavAgent {
+	pub fn new(can_jump: bool) -> Self {
+		Self { path: Vec::new(), can_jump, active: true, repath: Timer::from_seconds(REPATH_TIME, true) }
+	}
+
+	// World position of the next tile on the path, dropping tiles once they're reached
+	pub fn next_waypoint(&mut self, pos: Vec3) -> Option<Vec2> {
+		if let (Some(here), Some(next)) = (tile_at(pos), self.path.first()) {
+			if here == *next {
+				self.path.remove(0);
+			}
+		}
+		self.path.first().map(|(x, y)| tile_center(*x, *y))
+	}
+}
+
+pub struct NavPlugin;
+impl Plugin for NavPlugin {
+	fn build (&self, app: &mut App) {
+		app.init_resource::<NavGrid>()
+			.add_system(rebuild_nav.run_in_state(GameState::Playing))
+			.add_system(plan_paths.run_in_state(GameState::Playing));
+	}
+}
+
+fn rebuild_nav(
+	active_room: Option<Res<ActiveRoom>>,
+	map_query: Query<&Map>,
+	blocker_query: Query<&Transform, With<NavBlocker>>,
+	mut nav: ResMut<NavGrid>,
+) {
+	let (active_room, map) = match (active_room, map_query.get_single()) {
+		(Some(room), Ok(map)) => (room, map),
+		_ => return,
+	};
+	if let Some(room) = map.room(active_room.0, active_room.1) {
+		let mut grid = room.room_coords;
+		for (x, y) in blocker_query.iter().filter_map(|blocker| tile_at(blocker.translation)) {
+			grid[y][x] = 'U';
+		}
+		if nav.grid != Some(grid) {
+			*nav = NavGrid::build(&grid);
+		}
+	}
+}
+
+fn plan_paths(
+	time: Res<Time>,
+	nav: Res<NavGrid>,
+	player_query: Query<&Transform, With<Player>>,
+	mut agent_query: Query<(&Transform, &mut NavAgent), Without<Player>>,
+) {
+	let goal = match player_query.get_single().ok().and_then(|p| tile_at(p.translation)).and_then(|t| nav.node_below(t)) {
+		Some(goal) => goal,
+		None => return,
+	};
+	for (transform, mut agent) in agent_query.iter_mut() {
+		if !agent.repath.tick(time.delta()).just_finished() || !agent.active {
+			continue;
+		}
+		let start = tile_at(transform.translation).and_then(|t| nav.node_below(t));
+		agent.path = start.and_then(|start| nav.find_path(start, goal, agent.can_jump)).unwrap_or_default();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn grid(rows: [&str; ROOM_HEIGHT]) -> [[char; ROOM_WIDTH]; ROOM_HEIGHT] {
+		let mut grid = [['-'; ROOM_WIDTH]; ROOM_HEIGHT];
+		for (i, row) in rows.iter().enumerate() {
+			for (j, c) in row.chars().enumerate() {
+				grid[i][j] = c;
+			}
+		}
+		grid
+	}
+
+	const ROOM: [&str; ROOM_HEIGHT] = [
+		"UUUUUUUUUUUUUUUU",
+		"U--------------U",
+		"U--------------U",
+		"U--------------U",
+		"U--------------U",
+		"U--------------U",
+		"U-------####---U",
+		"U--------------U",
+		"UUUUUUUUUUUUUUUU",
+	];
+
+	#[test]
+	fn jumpers_climb_onto_ledges_and_walkers_go_around() {
+		let nav = NavGrid::build(&grid(ROOM));
+		let path = nav.find_path((2, 7), (9, 5), true).unwrap();
+		assert_eq!(*path.last().unwrap(), (9, 5));
+		assert!(nav.find_path((2, 7), (9, 5), false).is_none());
+		//dropping off the ledge doesn't need a jump
+		assert!(nav.find_path((9, 5), (2, 7), false).is_some());
+	}
+
+	#[test]
+	fn mining_a_brick_opens_a_path() {
+		let mut rows = ROOM;
+		rows[7] = "U-----#--------U";
+		let blocked = NavGrid::build(&grid(rows));
+		assert!(blocked.find_path((2, 7), (10, 7), false).is_none());
+		rows[7] = "U--------------U";
+		let mined = NavGrid::build(&grid(rows));
+		assert_eq!(mined.find_path((2, 7), (10, 7), false).unwrap().len(), 8);
+	}
+}
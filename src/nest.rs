@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+	GameState,
+	enemy::EnemyStats,
+	enemy_def::{
+		EnemyRoster,
+		Loot,
+		spawn_enemy,
+	},
+	difficulty::difficulty_at,
+	level::{
+		ActiveRoom,
+		Brick,
+		Collider,
+		RoomTile,
+	},
+	player::Health,
+	combat::hit_stop_over,
+};
+
+const NEST_HEALTH: f32 = 150.;
+const NEST_TIME: f32 = 4.; //seconds between releases
+const NEST_CAP: usize = 3; //most of its enemies alive at once
+const NEST_TINT: Color = Color::rgb(0.55, 0.3, 0.6);
+
+// Brick that keeps letting enemies out while the player's in the room. Being a Brick, the pickaxe
+// and bomb fragments break it, and once it's gone it stays gone like any other brick. What it lets
+// out drops nothing, otherwise a nest would be an endless supply of bombs.
+#[derive(Component)]
+pub struct Nest {
+	spawns: char, //room tile of the enemy it releases
+	exit: Vec3, //where they come out
+	timer: Timer,
+	released: Vec<Entity>,
+}
+
+pub struct NestPlugin;
+impl Plugin for NestPlugin {
+	fn build (&self, app: &mut App) {
+		app.add_system(release_enemies.run_in_state(GameState::Playing).run_if(hit_stop_over));
+	}
+}
+
+pub fn spawn_nest(commands: &mut Commands, brick_sheet: Handle<TextureAtlas>, translation: Vec3, exit: Vec3, spawns: char, tile: RoomTile, health_scale: f32) -> Entity {
+	commands
+		.spawn_bundle(SpriteSheetBundle {
+			texture_atlas: brick_sheet,
+			sprite: TextureAtlasSprite {
+				index: 3,
+				color: NEST_TINT,
+				..default()
+			},
+			transform: Transform::from_translation(translation),
+			..default()
+		})
+		.insert(Nest { spawns, exit, timer: Timer::from_seconds(NEST_TIME, true), released: Vec::new() })
+		.insert(Health::from(NEST_HEALTH * health_scale))
+		.insert(Brick)
+		.insert(Collider)
+		.insert(tile)
+		.id()
+}
+
+fn release_enemies(
+	mut commands: Commands,
+	time: Res<Time>,
+	roster: Res<EnemyRoster>,
+	active_room: Option<Res<ActiveRoom>>,
+	enemy_query: Query<Entity, With<EnemyStats>>,
+	mut nest_query: Query<&mut Nest>,
+) {
+	let difficulty = match active_room {
+		Some(room) => difficulty_at(room.0, room.1),
+		None => return,
+	};
+	for mut nest in nest_query.iter_mut() {
+		if !nest.timer.tick(time.delta()).just_finished() {
+			continue;
+		}
+		//forget the ones that died
+		nest.released.retain(|e| enemy_query.get(*e).is_ok());
+		if nest.released.len() >= NEST_CAP {
+			continue;
+		}
+		if let Some(kind) = roster.get(nest.spawns) {
+			let enemy = spawn_enemy(&mut commands, kind, nest.exit, &difficulty).remove::<Loot>().id();
+			nest.released.push(enemy);
+		}
+	}
+}
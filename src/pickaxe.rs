@@ -0,0 +1,332 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use bevy::sprite::collide_aabb::collide;
+
+use crate::{
+	WIN_W,
+	WIN_H,
+	TILE_SIZE,
+	GameState,
+	loading::{
+		LoadingAssets,
+		LoadingAssetInfo,
+	},
+	level::RoomTile,
+	player::{
+		Player,
+		InventoryCount,
+		SwingTimer,
+	},
+	controls::{
+		Action,
+		Actions,
+	},
+};
+
+const SWING_FRAMES: f32 = 4.; //frames in the swing animation
+const ICON_SIZE: f32 = 50.;
+
+// Pickaxes get better in this order. Each tier hits harder, swings faster, reaches further and
+// can break tougher bricks (brick hardness comes from the biome, see biome.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PickaxeTier {
+	Wood,
+	Iron,
+	Diamond,
+}
+
+pub struct PickaxeStats {
+	pub name: &'static str,
+	pub damage: f32, //to enemies and the boss
+	pub brick_damage: f32,
+	pub swing_time: f32, //seconds between swings
+	pub reach: f32, //pixels past the miner
+	pub max_hardness: f32, //bricks harder than this just clang
+	pub price: f32, //bombs at an anvil
+	pub tint: Color,
+}
+
+const WOOD: PickaxeStats = PickaxeStats {
+	name: "wood",
+	damage: 25.,
+	brick_damage: 20.,
+	swing_time: 0.48,
+	reach: TILE_SIZE,
+	max_hardness: 1.5,
+	price: 0.,
+	tint: Color::rgb(0.65, 0.45, 0.25),
+};
+
+const IRON: PickaxeStats = PickaxeStats {
+	name: "iron",
+	damage: 40.,
+	brick_damage: 35.,
+	swing_time: 0.36,
+	reach: TILE_SIZE * 1.25,
+	max_hardness: 2.0,
+	price: 4.,
+	tint: Color::rgb(0.75, 0.75, 0.8),
+};
+
+const DIAMOND: PickaxeStats = PickaxeStats {
+	name: "diamond",
+	damage: 60.,
+	brick_damage: 60.,
+	swing_time: 0.26,
+	reach: TILE_SIZE * 1.5,
+	max_hardness: 2.5,
+	price: 8.,
+	tint: Color::rgb(0.4, 0.9, 1.0),
+};
+
+impl PickaxeTier {
+	pub fn stats(&self) -> &'static PickaxeStats {
+		match self {
+			PickaxeTier::Wood => &WOOD,
+			PickaxeTier::Iron => &IRON,
+			PickaxeTier::Diamond => &DIAMOND,
+		}
+	}
+
+	pub fn next(&self) -> Option<PickaxeTier> {
+		match self {
+			PickaxeTier::Wood => Some(PickaxeTier::Iron),
+			PickaxeTier::Iron => Some(PickaxeTier::Diamond),
+			PickaxeTier::Diamond => None,
+		}
+	}
+}
+
+// The pickaxe the miner is carrying, kept for the whole run
+pub struct Pickaxe {
+	pub tier: PickaxeTier,
+	cooldown: Timer,
+}
+
+impl Default for Pickaxe {
+	fn default() -> Self {
+		Self { tier: PickaxeTier::Wood, cooldown: Timer::from_seconds(WOOD.swing_time, false) }
+	}
+}
+
+impl Pickaxe {
+	pub fn stats(&self) -> &'static PickaxeStats {
+		self.tier.stats()
+	}
+
+	// Counts down the time between swings, true if a swing can start now
+	pub fn ready(&mut self, delta: std::time::Duration) -> bool {
+		self.cooldown.tick(delta).finished()
+	}
+
+	pub fn swung(&mut self) {
+		self.cooldown = Timer::from_seconds(self.stats().swing_time, false);
+	}
+
+	fn upgrade(&mut self) -> bool {
+		match self.tier.next() {
+			Some(tier) => {
+				self.tier = tier;
+				info!("got a {} pickaxe", tier.stats().name);
+				true
+			}
+			None => false,
+		}
+	}
+}
+
+// How tough a breakable tile is, the pickaxe needs at least this max_hardness to dent it
+#[derive(Component)]
+pub struct Hardness(pub f32);
+
+// A better pickaxe lying in a room ('P'), one tier up from whatever the miner has
+#[derive(Component)]
+pub struct PickaxePickup;
+
+// Anvil ('M') that sells the next tier for bombs
+#[derive(Component)]
+pub struct Anvil;
+
+#[derive(Component)]
+struct PickaxeIcon;
+
+pub struct PickaxeImage(Handle<Image>);
+
+pub struct PickaxePlugin;
+impl Plugin for PickaxePlugin {
+	fn build (&self, app: &mut App) {
+		app.init_resource::<Pickaxe>()
+			.add_enter_system(GameState::Loading, load_pickaxe_image)
+			.add_exit_system(GameState::MainMenu, reset_pickaxe)
+			.add_enter_system(GameState::Playing, spawn_pickaxe_icon)
+			.add_system_set(
+				ConditionSet::new()
+					.run_in_state(GameState::Playing)
+					.with_system(pick_up_pickaxes)
+					.with_system(buy_pickaxes)
+					.with_system(update_pickaxe_icon)
+					.into()
+			);
+	}
+}
+
+fn load_pickaxe_image(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut loading_assets: ResMut<LoadingAssets>,
+) {
+	let handle = asset_server.load("pickaxe-swing/100x100/100-pickaxe-swing-1.png");
+	loading_assets.insert(
+		handle.clone_untyped(),
+		LoadingAssetInfo::for_handle(handle.clone_untyped(), &asset_server),
+	);
+	commands.insert_resource(PickaxeImage(handle));
+}
+
+// Every run starts with the wooden one
+fn reset_pickaxe(mut commands: Commands) {
+	commands.insert_resource(Pickaxe::default());
+}
+
+pub fn spawn_pickaxe_pickup(commands: &mut Commands, image: &PickaxeImage, translation: Vec3, tile: RoomTile) {
+	commands
+		.spawn_bundle(SpriteBundle {
+			texture: image.0.clone(),
+			sprite: Sprite {
+				custom_size: Some(Vec2::splat(ICON_SIZE)),
+				..default()
+			},
+			transform: Transform::from_translation(translation),
+			..default()
+		})
+		.insert(PickaxePickup)
+		.insert(tile);
+}
+
+pub fn spawn_anvil(commands: &mut Commands, image: &PickaxeImage, translation: Vec3) {
+	commands
+		.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: Color::rgb(0.2, 0.2, 0.25),
+				custom_size: Some(Vec2::new(TILE_SIZE * 0.8, TILE_SIZE * 0.5)),
+				..default()
+			},
+			transform: Transform::from_translation(translation - Vec3::new(0., TILE_SIZE * 0.25, 0.)),
+			..default()
+		})
+		.insert(Anvil)
+		.with_children(|anvil| {
+			anvil.spawn_bundle(SpriteBundle {
+				texture: image.0.clone(),
+				sprite: Sprite {
+					custom_size: Some(Vec2::splat(ICON_SIZE * 0.8)),
+					..default()
+				},
+				transform: Transform::from_xyz(0., TILE_SIZE * 0.4, 1.),
+				..default()
+			});
+		});
+}
+
+// Current tier in the top corner, tinted to match it
+fn spawn_pickaxe_icon(mut commands: Commands, image: Res<PickaxeImage>, pickaxe: Res<Pickaxe>) {
+	commands
+		.spawn_bundle(SpriteBundle {
+			texture: image.0.clone(),
+			sprite: Sprite {
+				color: pickaxe.stats().tint,
+				custom_size: Some(Vec2::splat(ICON_SIZE)),
+				..default()
+			},
+			transform: Transform::from_xyz((WIN_W/2.) - (TILE_SIZE * 3.4), (WIN_H/2.) - (TILE_SIZE * 0.35), 999.),
+			..default()
+		})
+		.insert(PickaxeIcon);
+}
+
+fn update_pickaxe_icon(
+	pickaxe: Res<Pickaxe>,
+	mut icon_query: Query<&mut Sprite, With<PickaxeIcon>>,
+	mut pickup_query: Query<&mut Sprite, (With<PickaxePickup>, Without<PickaxeIcon>)>,
+	mut swing_query: Query<&mut SwingTimer>,
+) {
+	for mut sprite in icon_query.iter_mut() {
+		sprite.color = pickaxe.stats().tint;
+	}
+	//pickups show what they'd turn into
+	let next = pickaxe.tier.next().unwrap_or(pickaxe.tier);
+	for mut sprite in pickup_query.iter_mut() {
+		sprite.color = next.stats().tint;
+	}
+	//the swing animation keeps pace with the swing rate
+	let frame_time = std::time::Duration::from_secs_f32(pickaxe.stats().swing_time / SWING_FRAMES);
+	for mut timer in swing_query.iter_mut() {
+		if timer.duration() != frame_time {
+			timer.set_duration(frame_time);
+		}
+	}
+}
+
+fn pick_up_pickaxes(
+	mut commands: Commands,
+	mut pickaxe: ResMut<Pickaxe>,
+	player_query: Query<&Transform, With<Player>>,
+	pickup_query: Query<(Entity, &Transform), With<PickaxePickup>>,
+) {
+	let player = match player_query.get_single() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	for (entity, transform) in pickup_query.iter() {
+		if collide(player.translation, Vec2::splat(50.), transform.translation, Vec2::splat(ICON_SIZE)).is_some() {
+			//already the best one, leave it be
+			if pickaxe.upgrade() {
+				commands.entity(entity).despawn();
+			}
+		}
+	}
+}
+
+fn buy_pickaxes(
+	actions: Actions,
+	mut pickaxe: ResMut<Pickaxe>,
+	mut player_query: Query<(&Transform, &mut Player, &mut InventoryCount)>,
+	anvil_query: Query<&Transform, With<Anvil>>,
+) {
+	if !actions.just_pressed(Action::Interact) {
+		return;
+	}
+	let (transform, mut player, mut inventory) = match player_query.get_single_mut() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	let at_anvil = anvil_query.iter()
+		.any(|anvil| collide(transform.translation, Vec2::splat(50.), anvil.translation, Vec2::splat(TILE_SIZE)).is_some());
+	let next = match pickaxe.tier.next() {
+		Some(next) if at_anvil => next,
+		_ => return,
+	};
+	if player.spend_bombs(&mut inventory, next.stats().price) {
+		pickaxe.upgrade();
+	} else {
+		info!("a {} pickaxe costs {} bombs", next.stats().name, next.stats().price);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tiers_only_get_better() {
+		let tiers = [PickaxeTier::Wood, PickaxeTier::Iron, PickaxeTier::Diamond];
+		for pair in tiers.windows(2) {
+			let (low, high) = (pair[0].stats(), pair[1].stats());
+			assert_eq!(pair[0].next(), Some(pair[1]));
+			assert!(high.damage > low.damage && high.brick_damage > low.brick_damage);
+			assert!(high.swing_time < low.swing_time);
+			assert!(high.reach > low.reach && high.max_hardness > low.max_hardness);
+		}
+		assert_eq!(PickaxeTier::Diamond.next(), None);
+	}
+}
@@ -1,16 +1,19 @@
-use bevy::prelude::*;
+use bevy::{
+	asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+	prelude::*,
+	reflect::TypeUuid,
+};
 use iyes_loopless::prelude::*;
 use std::convert::From;
 use std::time::Duration;
 use bevy::sprite::collide_aabb::collide;
-use bevy::sprite::collide_aabb::Collision;
+use serde::Deserialize;
 use crate::{
 	WIN_W,
 	WIN_H,
 	TILE_SIZE,
 	ANIM_TIME,
 	PLAYER_SPEED,
-	JUMP_TIME,
 	FRAME_TIME,
 	INV_TIME,
 	GameState,
@@ -24,20 +27,141 @@ use crate::{
 	level::Map,
 	enemy::{
 		Enemy,
-		EnemySheet
+		EnemyStats,
 	},
 	level::BombItem,
 	level::HealthItem,
-	boss::Boss,
+	boss::{
+		Boss,
+		BOSS_CONTACT_DAMAGE,
+		BOSS_KNOCKBACK,
+		BOSS_SIZE,
+	},
+	boss_fight::Defeated,
+	combat::{
+		HitEvent,
+		hit_stop_over,
+	},
+	controls::{
+		Action,
+		Actions,
+		StepPresses,
+		clear_step_presses,
+	},
+	water::{
+		Breath,
+		SWIM_SPEED,
+	},
+	pickaxe::{
+		Pickaxe,
+		Hardness,
+	},
 };
 
+const AXE_KNOCKBACK: f32 = 8. * TILE_SIZE;
+const AXE_WIDTH: f32 = TILE_SIZE * 0.6;
+const PLAYER_HALF_WIDTH: f32 = TILE_SIZE * 0.45; //half the collision box in check_tile_collision
+const FRAGMENT_DAMAGE: f32 = 20.;
+const THROW_SPEED: f32 = 6. * TILE_SIZE; //a tap
+const THROW_CHARGED_SPEED: f32 = 13. * TILE_SIZE; //held for the full charge
+const THROW_CHARGE_TIME: f32 = 0.8;
+const DROP_SPEED: f32 = 1.5 * TILE_SIZE; //thrown while holding down, it's set at the miner's feet
+const BOMB_GRAVITY: f32 = 25. * TILE_SIZE;
+const BOMB_BOUNCE: f32 = 0.45; //fraction of the speed kept off a wall, floor or ceiling
+const BOMB_SETTLE_SPEED: f32 = 2. * TILE_SIZE; //landing slower than this it stops bouncing
+const BOMB_FRICTION: f32 = 8. * TILE_SIZE; //slowing per second while rolling
+const BOMB_SIZE: f32 = TILE_SIZE * 0.3;
+
+// How the jump feels, loaded from assets/jump.ron and picked up again when it's edited.
+// Speeds are in tiles per second.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "c4a7e2d1-5b8f-4e3a-9d6c-1f2b3a4e5d6f"]
+pub struct JumpSettings {
+	pub jump_speed: f32,
+	pub gravity: f32, //tiles per second, per second
+	pub coyote_time: f32, //seconds after walking off a ledge that a jump still works
+	pub buffer_time: f32, //seconds a jump pressed before landing is remembered for
+	pub release_cut: f32, //upwards speed kept when jump is let go early
+	pub apex_speed: f32, //below this vertical speed the jump counts as at its peak
+	pub apex_gravity: f32, //gravity multiplier at the peak, under 1 hangs in the air a little
+}
+
+impl Default for JumpSettings {
+	fn default() -> Self {
+		Self {
+			jump_speed: 12., //same launch as the old fixed jump
+			gravity: 25.,
+			coyote_time: 0.1,
+			buffer_time: 0.12,
+			release_cut: 0.45,
+			apex_speed: 2.,
+			apex_gravity: 0.5,
+		}
+	}
+}
+
+impl JumpSettings {
+	// Gravity for this frame, eased off around the top of a jump
+	fn gravity_at(&self, y_velocity: f32) -> f32 {
+		let gravity = self.gravity * TILE_SIZE;
+		if y_velocity.abs() < self.apex_speed * TILE_SIZE {
+			gravity * self.apex_gravity
+		} else {
+			gravity
+		}
+	}
+}
+
+// jump.ron is the only .ron asset, so this takes the whole extension
+#[derive(Default)]
+pub struct JumpSettingsLoader;
+
+impl AssetLoader for JumpSettingsLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let settings: JumpSettings = ron::de::from_bytes(bytes)
+				.map_err(|e| anyhow::anyhow!("{}: {}", load_context.path().display(), e))?;
+			load_context.set_default_asset(LoadedAsset::new(settings));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["ron"]
+	}
+}
+
+pub struct JumpSettingsHandle(Handle<JumpSettings>);
+
 #[derive(Component)]
 pub struct Player{
 	y_velocity: f32,
 	x_velocity: f32,
 	grounded: bool,
+	facing: f32, //1 for right, -1 for left
+	coyote: f32, //seconds left to jump after leaving the ground
+	jump_buffer: f32, //seconds left on a jump pressed in the air
+	jumping: bool, //rising from a jump that can still be cut short
 	bombs: f32,
 	swing: bool,
+	throw_charge: f32, //seconds Throw has been held, the bomb goes when it's let go
+}
+
+impl Player {
+	// Takes n bombs for a purchase, false (and nothing spent) if there aren't enough
+	pub fn spend_bombs(&mut self, inventory: &mut InventoryCount, n: f32) -> bool {
+		if self.bombs < n {
+			return false;
+		}
+		self.bombs -= n;
+		inventory.b_count -= n;
+		info!("bombs left: {}", self.bombs);
+		true
+	}
 }
 
 //BOMB
@@ -69,7 +193,7 @@ pub struct SwingTimer(Timer);
 pub struct InvincibilityTimer(Timer);
 #[derive(Component)]
 pub struct Health{
-	health: f32,
+	pub health: f32,
 }
 
 impl Health {
@@ -78,6 +202,12 @@ impl Health {
 	}
 }
 
+impl From<f32> for Health {
+	fn from(health: f32) -> Self {
+		Self {health}
+	}
+}
+
 #[derive(Deref, DerefMut)]
 pub struct HealthAtlas(Handle<TextureAtlas>);
 
@@ -136,13 +266,18 @@ impl Plugin for PlayerPlugin {
     //every_second.add_system(check_enemy_collision.run_in_state(GameState::Playing)); //.add_system(update_health.run_in_state(GameState::Playing));
 		//every_second.add_system(check_enemy_collision.run_in_state(GameState::Playing));
 
+		//presses wait until a step has actually run with them, hit-stops included
+		let mut after_frame = SystemStage::parallel();
+		after_frame.add_system(clear_step_presses.run_if(hit_stop_over));
+
 		every_frame.add_system_set(
 				ConditionSet::new()
 					.run_in_state(GameState::Playing)
+					.run_if(hit_stop_over)
 					.with_system(move_player)
 					.with_system(animate_player)
 					.with_system(enter_door)
-					.with_system(swing_axe)
+					.with_system(swing_pickaxe)
 					.with_system(animate_swing)
 					.with_system(update_health)
 					.with_system(check_enemy_collision)
@@ -153,14 +288,19 @@ impl Plugin for PlayerPlugin {
 					.with_system(bomb_throw)
 					.with_system(move_bomb)
 					.with_system(enter_new_room)
-					.with_system(damage_walls)
 					.with_system(spawn_fragment)
 					.with_system(fragment_movement)
 					.with_system(update_count)
 					//.with_system(my_fixed_update)  //This tests the frame times for this system, if that ever comes up
 					.into()
 					); //moving
-		app.add_enter_system(GameState::Loading, load_player_sheet)
+		//the default jump stands in until jump.ron loads, and stays if it doesn't
+		app.add_asset::<JumpSettings>()
+			.init_asset_loader::<JumpSettingsLoader>()
+			.insert_resource(JumpSettings::default())
+			.add_enter_system(GameState::Loading, load_jump_settings)
+			.add_system(update_jump_settings)
+			.add_enter_system(GameState::Loading, load_player_sheet)
 			.add_enter_system(GameState::Playing, spawn_player)
 			.add_enter_system(GameState::Loading, load_health_sheet)
 			.add_enter_system(GameState::Playing, spawn_health)
@@ -186,6 +326,7 @@ impl Plugin for PlayerPlugin {
 				CoreStage::Update,
 				"FixedStepFrame",
 				FixedTimestepStage::from_stage(Duration::from_micros(16667), every_frame) // ~1 frame at 60 fps
+					.with_stage(after_frame)
 					
 				
 			)
@@ -203,6 +344,41 @@ impl Plugin for PlayerPlugin {
     println!("Overstepped by {:?} ({}%).", info.remaining(), info.overstep() * 100.0);
 }**/
 
+fn load_jump_settings(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut loading_assets: ResMut<LoadingAssets>,
+) {
+	let handle: Handle<JumpSettings> = asset_server.load("jump.ron");
+	loading_assets.insert(
+		handle.clone_untyped(),
+		LoadingAssetInfo::for_handle(handle.clone_untyped(), &asset_server),
+	);
+	commands.insert_resource(JumpSettingsHandle(handle));
+}
+
+// Copies jump.ron into the settings the player uses whenever it loads or is edited
+fn update_jump_settings(
+	mut events: EventReader<AssetEvent<JumpSettings>>,
+	assets: Res<Assets<JumpSettings>>,
+	handle: Option<Res<JumpSettingsHandle>>,
+	mut jump: ResMut<JumpSettings>,
+) {
+	let handle = match handle {
+		Some(handle) => handle,
+		None => return,
+	};
+	for event in events.iter() {
+		if let AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed } = event {
+			if *changed == handle.0 {
+				if let Some(settings) = assets.get(&handle.0) {
+					*jump = *settings;
+				}
+			}
+		}
+	}
+}
+
 fn load_player_sheet(
 	mut commands: Commands,
 	asset_server: Res<AssetServer>,
@@ -242,42 +418,78 @@ fn spawn_player(
 		.insert(Velocity::new())
 		.insert(InvincibilityTimer(Timer::from_seconds(INV_TIME, false)))
 		.insert(Health::new())
+		.insert(Breath::new())
 		.insert(InventoryCount::new())
 		.insert(Player{
 			grounded: false,
+			facing: 1.,
+			coyote: 0.,
+			jump_buffer: 0.,
+			jumping: false,
 			y_velocity: -1.0,
 			x_velocity: 0.,
 			bombs: 3., //starting with 3 bombs for testing
 			swing: false,
+			throw_charge: 0.,
 		});
 }
 
 fn move_player(
 	_time: Res<Time>,
-	input: Res<Input<KeyCode>>,
+	actions: Actions,
+	presses: Res<StepPresses>,
+	jump: Res<JumpSettings>,
 	collision: Query<&Transform, (With<Collider>, Without<Player>)>,
-	mut player: Query<(&mut Player, &mut Transform)>,
+	mut player: Query<(&mut Player, &mut Transform, &Breath)>,
 ){
-	for (mut player, mut transform) in player.iter_mut() {
+	for (mut player, mut transform, breath) in player.iter_mut() {
+		//water slows everything down and lets the player paddle upwards
+		let drag = if breath.swimming { SWIM_SPEED } else { 1. };
+
+		//a jump still counts a moment after walking off a ledge, or a moment before landing
+		player.coyote = if player.grounded { jump.coyote_time } else { player.coyote - FRAME_TIME };
+		player.jump_buffer = if presses.pressed(Action::Jump) { jump.buffer_time } else { player.jump_buffer - FRAME_TIME };
+
+		if breath.swimming && presses.pressed(Action::Jump) {
+			player.y_velocity = drag * jump.jump_speed * TILE_SIZE;
+		} else if player.jump_buffer > 0. && player.coyote > 0. {
+			player.y_velocity = jump.jump_speed * TILE_SIZE;
+			player.jump_buffer = 0.;
+			player.coyote = 0.;
+			player.jumping = true;
+		}
 
-		if player.grounded && input.pressed(KeyCode::Space) { //changed to "pressed" instead of "just_pressed" because sometimes the jump wasn't working. Now you can hold space to jump when you hit the ground, but this seems acceptable.
-			player.y_velocity = JUMP_TIME * PLAYER_SPEED * TILE_SIZE * FRAME_TIME;
+		//letting go early cuts the jump short
+		if player.jumping && player.y_velocity > 0. && !actions.pressed(Action::Jump) {
+			player.y_velocity *= jump.release_cut;
+			player.jumping = false;
+		}
+		if player.y_velocity <= 0. {
+			player.jumping = false;
 		}
 
-		player.y_velocity += -25.0 * TILE_SIZE * FRAME_TIME;
+		let gravity = if breath.swimming { jump.gravity * TILE_SIZE } else { jump.gravity_at(player.y_velocity) };
+		player.y_velocity -= gravity * drag * FRAME_TIME;
+		if breath.swimming {
+			player.y_velocity = player.y_velocity.max(-PLAYER_SPEED * TILE_SIZE * drag);
+		}
 
 		let deltay = player.y_velocity * FRAME_TIME;
 		
 		let mut deltax = 0.0;
 
-		if input.pressed(KeyCode::A) {
+		if actions.pressed(Action::MoveLeft) {
 			deltax -= 1. * PLAYER_SPEED * TILE_SIZE * FRAME_TIME;
 		}
 
-		if input.pressed(KeyCode::D) {
+		if actions.pressed(Action::MoveRight) {
 			deltax += 1. * PLAYER_SPEED * TILE_SIZE * FRAME_TIME;
 		}
+		deltax *= drag;
 		player.x_velocity = deltax;
+		if deltax != 0. {
+			player.facing = deltax.signum();
+		}
 		let target = transform.translation + Vec3::new(deltax, 0., 0.);
 		if check_tile_collision(target, &collision){
 			transform.translation = target;
@@ -288,8 +500,9 @@ fn move_player(
 			transform.translation = target;
 			player.grounded = false;
 		}else{
+			//bumping your head isn't landing
+			player.grounded = player.y_velocity < 0.;
 			player.y_velocity = 0.0;
-			player.grounded = true;
 		}
 	}
 }
@@ -361,13 +574,13 @@ fn enter_door(
 	mut commands: Commands,
 	player: Query<&Transform, With<Player>>,
 	door: Query<&Transform, With<Door>>,
-	input: Res<Input<KeyCode>>,
+	presses: Res<StepPresses>,
 ) {
 	for player_transform in player.iter() {
 		//let door_transform = door.single();
 		for door_transform in door.iter()
 		{
-		if input.just_pressed(KeyCode::W) && collide(player_transform.translation, Vec2::splat(50.), door_transform.translation, Vec2::splat(50.)).is_some() {
+		if presses.pressed(Action::Interact) && collide(player_transform.translation, Vec2::splat(50.), door_transform.translation, Vec2::splat(50.)).is_some() {
 			info!("door open!");
 			commands.insert_resource(NextState(GameState::Credits));
 		}
@@ -376,109 +589,118 @@ fn enter_door(
 }
 
 pub fn check_enemy_collision(
-	mut commands: Commands,
-	_enemy_sheet: Res<EnemySheet>,
-	enemy_query: Query<&Transform, (With<Enemy>, Without<Player>)>,
-	boss_query: Query<&Transform, (With<Boss>, Without<Player>)>,
-	mut player_query: Query<
-		(
-			Entity, 
-			&Transform, 
-			&mut Health, 
-			&mut InvincibilityTimer,
-		),
-		(With<Player>,
-		Without<Enemy>)
-		>,
-	//mut health: Query<(&mut TextureAtlasSprite,&Handle<TextureAtlas>,),With<Health>>,
-	//texture_atlases: Res<Assets<TextureAtlas>>,
-	
+	mut hits: EventWriter<HitEvent>,
+	enemy_query: Query<(&Transform, &EnemyStats), (With<Enemy>, Without<Player>)>,
+	boss_query: Query<&Transform, (With<Boss>, Without<Defeated>, Without<Player>)>,
+	mut player_query: Query<(Entity, &Transform, &mut InvincibilityTimer), (With<Player>, Without<Enemy>)>,
 ) {
-	let (player_entity, player_transform, mut player_health, mut inv_timer) = player_query.single_mut();
-	//let (mut sprite, texture_atlas_handle) = health.single_mut();
-
-	for enemy_transform in enemy_query.iter() {
-		if collide(player_transform.translation, Vec2::splat(50.), enemy_transform.translation, Vec2::splat(50.)).is_some() && inv_timer.finished() {
-  				inv_timer.reset(); //reset the invincibility
-  				player_health.health -= 20.;
-  				//call update health here for more efficiency 
-  			
-  				//let texture_atlas = texture_atlases.get(texture_atlas_handle).unwrap();
-  				//let hs_len : usize = texture_atlas.textures.len() as usize;
-  				//let c_health : usize = (player_health.health/10.).round() as usize;
-  				//sprite.index = hs_len - c_health; //Use health to determine the index of the health sprite to show
-  	
-  				info!("{}", player_health.health);
-  				if player_health.health <= 0. {
-  					//player dies
-  					commands.insert_resource(NextState(GameState::GameOver));
-  					commands.entity(player_entity).despawn();
-  				}
-  			}
+	let (player_entity, player_transform, mut inv_timer) = player_query.single_mut();
+
+	//only one hit can land per invincibility window, so stop at the first thing touching us
+	if inv_timer.finished() {
+		let enemy_hit = enemy_query.iter()
+			.find(|(enemy_transform, _)| collide(player_transform.translation, Vec2::splat(50.), enemy_transform.translation, Vec2::splat(50.)).is_some())
+			.map(|(enemy_transform, stats)| HitEvent::from_point(player_entity, stats.damage, stats.knockback, enemy_transform.translation, player_transform.translation));
+		let boss_hit = || boss_query.iter()
+			.find(|boss_transform| collide(player_transform.translation, Vec2::splat(50.), boss_transform.translation, BOSS_SIZE.into()).is_some())
+			.map(|boss_transform| HitEvent::from_point(player_entity, BOSS_CONTACT_DAMAGE, BOSS_KNOCKBACK, boss_transform.translation, player_transform.translation));
+		if let Some(hit) = enemy_hit.or_else(boss_hit) {
+			hits.send(hit);
+		}
 	}
 	inv_timer.tick(Duration::from_secs_f32(FRAME_TIME)); //tick the invincibility timer after we're done checking collision
+}
+
+// Which way a swing goes, up or down while one is held, otherwise the way the miner faces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwingAim {
+	Forward,
+	Up,
+	Down,
+}
 
-	for boss_transform in boss_query.iter() {
-		if collide(player_transform.translation, Vec2::splat(50.), boss_transform.translation, Vec2::new(260.,100.)).is_some() && inv_timer.finished() {
-  				inv_timer.reset(); //reset the invincibility
-  				player_health.health -= 20.;
-  				//call update health here for more efficiency 
-  			
-  				//let texture_atlas = texture_atlases.get(texture_atlas_handle).unwrap();
-  				//let hs_len : usize = texture_atlas.textures.len() as usize;
-  				//let c_health : usize = (player_health.health/10.).round() as usize;
-  				//sprite.index = hs_len - c_health; //Use health to determine the index of the health sprite to show
-  	
-  				info!("{}", player_health.health);
-  				if player_health.health <= 0. {
-  					//player dies
-  					commands.insert_resource(NextState(GameState::GameOver));
-  					commands.entity(player_entity).despawn();
-  				}
-  			}
+impl SwingAim {
+	fn direction(&self, facing: f32) -> Vec2 {
+		match self {
+			SwingAim::Forward => Vec2::new(facing, 0.),
+			SwingAim::Up => Vec2::Y,
+			SwingAim::Down => -Vec2::Y,
+		}
 	}
-	inv_timer.tick(Duration::from_secs_f32(FRAME_TIME)); //tick the invincibility timer after we're done checking collision
 }
 
-pub fn swing_axe(
-	mut enemy_query: Query<(Entity, &Transform, &mut Health), (With<Enemy>, Without<Player>)>,
-	player_query: Query<&Transform, (With<Player>, Without<Enemy>)>,
-	input: Res<Input<KeyCode>>,
-	mut commands: Commands,
-) {
+// Box the pickaxe sweeps through, just beyond the miner's own collision box on the aimed side
+pub fn swing_hitbox(pos: Vec3, facing: f32, aim: SwingAim, reach: f32) -> (Vec3, Vec2) {
+	let dir = aim.direction(facing);
+	let offset = dir * (PLAYER_HALF_WIDTH + reach / 2.);
+	let size = if aim == SwingAim::Forward {
+		Vec2::new(reach, AXE_WIDTH)
+	} else {
+		Vec2::new(AXE_WIDTH, reach)
+	};
+	(pos + offset.extend(0.), size)
+}
 
-	for player_transform in player_query.iter() {
-		for (enemy_entity, enemy_transform, mut enemy_health) in enemy_query.iter_mut() {
-			let collision = collide(player_transform.translation, Vec2::splat(150.), enemy_transform.translation, Vec2::splat(50.));
-			if input.just_pressed(KeyCode::E) && collision.is_some() {
-				match collision.unwrap() {
-					Collision::Left => {
-						enemy_health.health -= 25.;
-						info!("{}", enemy_health.health);
-						if enemy_health.health <= 0. {
-							commands.entity(enemy_entity).despawn();
-						}
-					}
-					Collision::Inside => {
-						enemy_health.health -= 25.;
-						info!("{}", enemy_health.health);
-						if enemy_health.health <= 0. {
-							commands.entity(enemy_entity).despawn();
-						}
-					}
-					_ => {
-						//nothing
-					}
-				}
+// One swing hits every enemy in the box, the boss, and the nearest brick the pickaxe can dent
+pub fn swing_pickaxe(
+	enemy_query: Query<(Entity, &Transform), (With<Enemy>, Without<Player>)>,
+	boss_query: Query<(Entity, &Transform), (With<Boss>, Without<Player>)>,
+	brick_query: Query<(Entity, &Transform, Option<&Hardness>), (With<Brick>, With<Health>, Without<Player>)>,
+	player_query: Query<(&Player, &Transform)>,
+	actions: Actions,
+	presses: Res<StepPresses>,
+	mut pickaxe: ResMut<Pickaxe>,
+	mut hits: EventWriter<HitEvent>,
+) {
+	let ready = pickaxe.ready(Duration::from_secs_f32(FRAME_TIME));
+	if !ready || !presses.pressed(Action::Swing) {
+		return;
+	}
+	pickaxe.swung();
+	let stats = pickaxe.stats();
+	//holding down or up only aims the swing
+	let aim = if actions.pressed(Action::DigDown) {
+		SwingAim::Down
+	} else if actions.pressed(Action::AimUp) {
+		SwingAim::Up
+	} else {
+		SwingAim::Forward
+	};
+	for (player, player_transform) in player_query.iter() {
+		let (center, size) = swing_hitbox(player_transform.translation, player.facing, aim, stats.reach);
+		let dir = aim.direction(player.facing);
+
+		for (enemy_entity, enemy_transform) in enemy_query.iter() {
+			if collide(center, size, enemy_transform.translation, Vec2::splat(50.)).is_some() {
+				hits.send(HitEvent::towards(enemy_entity, stats.damage, AXE_KNOCKBACK, dir));
 			}
 		}
+		//the turtle is too heavy to knock back
+		for (boss_entity, boss_transform) in boss_query.iter() {
+			if collide(center, size, boss_transform.translation, BOSS_SIZE.into()).is_some() {
+				hits.send(HitEvent { target: boss_entity, damage: stats.damage, knockback: Vec2::ZERO });
+			}
+		}
+		let brick = brick_query.iter()
+			.filter(|(_, brick, _)| collide(center, size, brick.translation, Vec2::splat(TILE_SIZE)).is_some())
+			.min_by(|(_, a, _), (_, b, _)| a.translation.distance(center).total_cmp(&b.translation.distance(center)));
+		match brick {
+			//too hard for this pickaxe, it just bounces off
+			Some((_, _, Some(hardness))) if hardness.0 > stats.max_hardness => {
+				info!("this needs a better pickaxe");
+			}
+			Some((brick_entity, _, _)) => {
+				hits.send(HitEvent { target: brick_entity, damage: stats.brick_damage, knockback: Vec2::ZERO });
+			}
+			None => {}
+		}
 	}
 }
 
 fn animate_swing( //not complete yet
 	time: Res<Time>,
 	texture_atlases: Res<Assets<TextureAtlas>>,
-	input: Res<Input<KeyCode>>,
+	presses: Res<StepPresses>,
 	mut player: Query<
 		(
 			&mut Player,
@@ -494,7 +716,7 @@ fn animate_swing( //not complete yet
 	//info!("tick");
 	//let (entity, mut bomb, mut sprite, texture_atlas_handle, mut timer) = bomb.single_mut();
 	for (mut player, mut sprite, texture_atlas_handle, mut timer, _transform) in player.iter_mut() {
-		if player.x_velocity == 0.0 && (input.just_pressed(KeyCode::E) || player.swing){
+		if player.x_velocity == 0.0 && (presses.pressed(Action::Swing) || player.swing){
 			
 			if !player.swing || sprite.index < 8{
 				//info!("setting to 8");
@@ -627,111 +849,116 @@ fn player_fire_system(
 	}
 }*/
 
+// Launch velocity for a bomb: an arc the way the miner faces, a steep lob when holding up and a
+// gentle roll off the feet when holding down. Charging only adds speed to the first two.
+pub fn throw_velocity(facing: f32, aim: SwingAim, charge: f32) -> Vec2 {
+	let speed = THROW_SPEED + (THROW_CHARGED_SPEED - THROW_SPEED) * (charge / THROW_CHARGE_TIME).clamp(0., 1.);
+	match aim {
+		SwingAim::Forward => Vec2::new(facing, 0.8).normalize() * speed,
+		SwingAim::Up => Vec2::new(facing * 0.3, 1.).normalize() * speed,
+		SwingAim::Down => Vec2::new(facing * DROP_SPEED, 0.),
+	}
+}
+
+// Velocity after hitting a surface head on, None once it's too slow to come back off it
+pub fn bounce(velocity: f32) -> Option<f32> {
+	if velocity.abs() < BOMB_SETTLE_SPEED {
+		None
+	} else {
+		Some(-velocity * BOMB_BOUNCE)
+	}
+}
+
+// Holding Throw charges it up, letting go throws
 fn bomb_throw(
 	mut commands: Commands,
-	kb: Res<Input<KeyCode>>,
-	//game_textures: Res<GameTextures>,
-	query: Query<&Transform, With<Player>>,
+	actions: Actions,
+	presses: Res<StepPresses>,
 	bomb_sheet: Res<BombSheet>,
-	mut player: Query<(&mut Player, &mut InventoryCount), With<Player>>,
+	mut player_query: Query<(&mut Player, &mut InventoryCount, &Transform)>,
 ){
-	if let Ok(player_tf) = query.get_single(){
-		if kb.just_pressed(KeyCode::F){
-			let (x,y) = (player_tf.translation.x, player_tf.translation.y);
-			let (mut player, mut inventory) = player.single_mut();
-			//info!("Bomb dropped");
-			if player.bombs > 0. {
-				if player.x_velocity < 0.{
-					commands
-					.spawn_bundle(SpriteSheetBundle {
-						texture_atlas: bomb_sheet.clone(),
-						sprite: TextureAtlasSprite {
-							index: 0,
-							..default()
-						},
-						transform: Transform::from_xyz(x, y , 900.), 
-						//for throw, change the velocities for projectile/parabola trajectory and have spawn from player y (center of player sprite)
-						..default()
-					})
-					.insert(AnimationTimer(Timer::from_seconds(ANIM_TIME, true)))
-					//.insert(Velocity::new())
-					.insert(Bomb{
-						grounded: false,
-						y_velocity: 0., //-1.0,
-						x_velocity: -1.,
-					});
-					player.bombs = player.bombs - 1.;
-					inventory.b_count -= 1.0;
-					info!("bombs left: {}", player.bombs);
-				}else{
-					commands
-					.spawn_bundle(SpriteSheetBundle {
-						texture_atlas: bomb_sheet.clone(),
-						sprite: TextureAtlasSprite {
-							index: 0,
-							..default()
-						},
-						transform: Transform::from_xyz(x, y, 900.), 
-						//for throw, change the velocities for projectile/parabola trajectory and have spawn from player y (center of player sprite)
-						..default()
-					})
-					.insert(AnimationTimer(Timer::from_seconds(ANIM_TIME, true)))
-					//.insert(Velocity::new())
-					.insert(Bomb{
-						grounded: false,
-						y_velocity: 0., //-1.0,
-						x_velocity: 0.,
-					});
-					player.bombs = player.bombs - 1.;
-					inventory.b_count -= 1.0;
-					info!("bombs left: {}", player.bombs);
-				}
-				
-					
-			}
-			
-		}
+	let (mut player, mut inventory, player_tf) = match player_query.get_single_mut() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	//a tap that's already been let go still counts, it's thrown next step
+	if actions.pressed(Action::Throw) || presses.pressed(Action::Throw) {
+		player.throw_charge += FRAME_TIME;
+		return;
 	}
-	
+	if player.throw_charge == 0. {
+		return;
+	}
+	let charge = player.throw_charge;
+	player.throw_charge = 0.;
+	if player.bombs <= 0. {
+		return;
+	}
+
+	let aim = if actions.pressed(Action::DigDown) {
+		SwingAim::Down
+	} else if actions.pressed(Action::AimUp) {
+		SwingAim::Up
+	} else {
+		SwingAim::Forward
+	};
+	//carries the miner's run, which is stored per frame
+	let velocity = throw_velocity(player.facing, aim, charge) + Vec2::new(player.x_velocity / FRAME_TIME, 0.);
+	commands
+		.spawn_bundle(SpriteSheetBundle {
+			texture_atlas: bomb_sheet.clone(),
+			sprite: TextureAtlasSprite {
+				index: 0,
+				..default()
+			},
+			transform: Transform::from_xyz(player_tf.translation.x, player_tf.translation.y, 900.),
+			..default()
+		})
+		.insert(AnimationTimer(Timer::from_seconds(ANIM_TIME, true)))
+		.insert(Bomb{
+			grounded: false,
+			y_velocity: velocity.y,
+			x_velocity: velocity.x,
+		});
+	player.bombs = player.bombs - 1.;
+	inventory.b_count -= 1.0;
+	info!("bombs left: {}", player.bombs);
 }
 
+// Bombs fly under gravity, bounce off colliders losing speed each time, then roll to a stop
 fn move_bomb(
-    mut bomb_query: Query<(Entity, &mut Bomb, &mut Transform), (With<Bomb>,Without<BombItem>, Without<Player>, Without<Enemy>, Without<Brick>)>,
-    collision: Query<&Transform, (With<Collider>, Without<Bomb>)>,
+	mut bomb_query: Query<(&mut Bomb, &mut Transform), (With<Bomb>,Without<BombItem>, Without<Player>, Without<Enemy>, Without<Brick>)>,
+	collision: Query<&Transform, (With<Collider>, Without<Bomb>)>,
 ){
-    for(bomb_ent, mut bomb, mut transform) in bomb_query.iter_mut(){
-        
-        let mut deltax = 0.5;
-        let mut deltay = 0.0;
-        if bomb.grounded {
-            deltax = 0.0;
-        }
-        deltax = if bomb.x_velocity < 0. {
-			-deltax * TILE_SIZE * FRAME_TIME * 10.
-		}else {
-			deltax * TILE_SIZE * FRAME_TIME * 10.
-		};
-        deltay = deltay  * TILE_SIZE * FRAME_TIME * 10.;
-        
-        bomb.x_velocity = deltax;
-        
-        bomb.y_velocity += -0.5;
-        deltay += bomb.y_velocity;
-
-        let target = transform.translation + Vec3::new(deltax, 0., 0.);
-        if check_tile_collision_bomb(target, &collision){
-            transform.translation = target;
-        }else{
-            deltax = 0.0;
-        }
-        bomb.y_velocity = deltay;
-        let target = transform.translation + Vec3::new(0., deltay, 0.);
-        if check_tile_collision_bomb(target, &collision){
-            transform.translation = target;
-        }else{
-            bomb.grounded = true;
-        }
-    }
+	for (mut bomb, mut transform) in bomb_query.iter_mut() {
+		let target = transform.translation + Vec3::new(bomb.x_velocity * FRAME_TIME, 0., 0.);
+		if check_tile_collision_bomb(target, &collision) {
+			transform.translation = target;
+		} else {
+			bomb.x_velocity = bounce(bomb.x_velocity).unwrap_or(0.);
+		}
+
+		bomb.y_velocity -= BOMB_GRAVITY * FRAME_TIME;
+		let target = transform.translation + Vec3::new(0., bomb.y_velocity * FRAME_TIME, 0.);
+		if check_tile_collision_bomb(target, &collision) {
+			transform.translation = target;
+			bomb.grounded = false;
+		} else {
+			let falling = bomb.y_velocity < 0.;
+			match bounce(bomb.y_velocity) {
+				Some(velocity) => bomb.y_velocity = velocity,
+				None => {
+					bomb.y_velocity = 0.;
+					bomb.grounded = falling;
+				}
+			}
+		}
+
+		if bomb.grounded {
+			let slowed = (bomb.x_velocity.abs() - BOMB_FRICTION * FRAME_TIME).max(0.);
+			bomb.x_velocity = slowed.copysign(bomb.x_velocity);
+		}
+	}
 }
 
 fn check_tile_collision_bomb(
@@ -741,7 +968,7 @@ fn check_tile_collision_bomb(
     for wall in wall_collide.iter(){
         let collision = collide(
             pos,
-            Vec2::splat(TILE_SIZE * 0.1),
+            Vec2::splat(BOMB_SIZE),
             wall.translation,
             Vec2::splat(TILE_SIZE)
         );
@@ -796,52 +1023,6 @@ fn spawn_bomb(
 
 }
 
-pub fn damage_walls(
-	mut wall_query: Query<(Entity, &Transform, &mut Health), (With<Brick>, Without<Player>, Without<Enemy>)>,
-	player_query: Query<&Transform, (With<Player>, Without<Enemy>, Without<Brick>)>,
-	input: Res<Input<KeyCode>>,
-	mut commands: Commands,
-) {
-	for player_transform in player_query.iter() {
-		for (wall_entity, wall_transform, mut wall_health) in wall_query.iter_mut() {
-			let collision = collide(player_transform.translation, Vec2::new(100., 60.), wall_transform.translation, Vec2::splat(80.));
-			if input.just_pressed(KeyCode::E) && collision.is_some() {
-				match collision.unwrap() {
-					Collision::Left => {
-						wall_health.health -= 20.;
-						info!("{}", wall_health.health);
-						//info!("Left");
-						if wall_health.health <= 0. {
-							commands.entity(wall_entity).despawn();
-						}
-					}
-					Collision::Right => {
-						wall_health.health -= 20.;
-						info!("{}", wall_health.health);
-						//info!("Right");
-						if wall_health.health <= 0. {
-							commands.entity(wall_entity).despawn();
-						}
-					}
-					Collision::Top => {
-						if input.just_pressed(KeyCode::S) {
-							wall_health.health -= 20.;
-							info!("{}", wall_health.health);
-							//info!("Right");
-							if wall_health.health <= 0. {
-								commands.entity(wall_entity).despawn();
-							}
-						}
-					}
-					_ => {
-						//nothing
-					}
-				}
-			}
-		}
-	}
-}
-
 fn animate_bomb( //not complete yet
 	time: Res<Time>,
 	texture_atlases: Res<Assets<TextureAtlas>>,
@@ -951,7 +1132,7 @@ fn enter_new_room(
 				player_transform.translation.x = WIN_W/2.0-(1.5 *TILE_SIZE); //avoid ooB
 			}
 			map.player_spawn = *player_transform;
-			map.y_coords -= 1_usize;
+			map.y_coords -= 1;
 			commands.insert_resource(NextState(GameState::Traverse));
 			info!("newroom up");
 		}
@@ -966,7 +1147,7 @@ fn enter_new_room(
 				player_transform.translation.y = WIN_H/2.0-(1.5 *TILE_SIZE); //avoid ooB
 			}
 			map.player_spawn = *player_transform;
-			map.x_coords -= 1_usize;
+			map.x_coords -= 1;
 			commands.insert_resource(NextState(GameState::Traverse));
 			info!("newroom left");
 		}
@@ -981,7 +1162,7 @@ fn enter_new_room(
 				player_transform.translation.y = WIN_H/2.0-(1.5 *TILE_SIZE); //avoid ooB
 			}
 			map.player_spawn = *player_transform;
-			map.x_coords += 1_usize;
+			map.x_coords += 1;
 			commands.insert_resource(NextState(GameState::Traverse));
 			info!("newroom right");
 		}
@@ -996,7 +1177,7 @@ fn enter_new_room(
 				player_transform.translation.x = WIN_W/2.0-(1.5 *TILE_SIZE); //avoid ooB
 			}
 			map.player_spawn = *player_transform;
-			map.y_coords += 1_usize;
+			map.y_coords += 1;
 			commands.insert_resource(NextState(GameState::Traverse));
 			info!("newroom down");
 		}
@@ -1109,7 +1290,9 @@ fn spawn_fragment(
 
 fn fragment_movement(
 	mut commands: Commands,
-	mut wall_query: Query<(Entity, &Transform, &mut Health), (With<Brick>, Without<Player>, Without<Enemy>)>,
+	mut hits: EventWriter<HitEvent>,
+	wall_query: Query<(Entity, &Transform), (With<Brick>, With<Health>, Without<Player>, Without<Enemy>)>,
+	boss_query: Query<(Entity, &Transform), (With<Boss>, Without<Fragment>)>,
 	time: Res<Time>,
 	input: Res<Input<KeyCode>>,
 	collision: Query<&Transform, (With<Collider>, Without<Fragment>)>,
@@ -1169,6 +1352,12 @@ fn fragment_movement(
 			deltay = deltay  * TILE_SIZE * FRAME_TIME * 10.;
 			
 			fragment.x_velocity = deltax;
+
+			if let Some((boss_entity, _)) = boss_query.iter().find(|(_, boss)| collide(transform.translation, Vec2::splat(30.), boss.translation, BOSS_SIZE.into()).is_some()) {
+				hits.send(HitEvent { target: boss_entity, damage: FRAGMENT_DAMAGE, knockback: Vec2::ZERO });
+				commands.entity(entity).despawn();
+				continue;
+			}
 			
 			fragment.y_velocity += -0.5;
 			deltay += fragment.y_velocity;
@@ -1176,15 +1365,9 @@ fn fragment_movement(
 			if check_tile_collision_frag(target, &collision){
 				transform.translation = target;
 			}else{
-				for (wall_entity, wall_transform, mut wall_health) in wall_query.iter_mut() {
-					let collision = collide(transform.translation, Vec2::splat(30.), wall_transform.translation, Vec2::splat(80.));
-					if collision.is_some() {
-								wall_health.health -= 20.;
-								info!("{}", wall_health.health);
-								
-								if wall_health.health <= 0. {
-									commands.entity(wall_entity).despawn();
-								}
+				for (wall_entity, wall_transform) in wall_query.iter() {
+					if collide(transform.translation, Vec2::splat(30.), wall_transform.translation, Vec2::splat(80.)).is_some() {
+						hits.send(HitEvent { target: wall_entity, damage: FRAGMENT_DAMAGE, knockback: Vec2::ZERO });
 					}
 				}
 				commands.entity(entity).despawn();
@@ -1194,15 +1377,9 @@ fn fragment_movement(
 			if check_tile_collision_frag(target, &collision){
 				transform.translation = target;
 			}else{
-				for (wall_entity, wall_transform, mut wall_health) in wall_query.iter_mut() {
-					let collision = collide(transform.translation, Vec2::splat(30.), wall_transform.translation, Vec2::splat(80.));
-					if collision.is_some() {
-								wall_health.health -= 20.;
-								info!("{}", wall_health.health);
-								
-								if wall_health.health <= 0. {
-									commands.entity(wall_entity).despawn();
-								}
+				for (wall_entity, wall_transform) in wall_query.iter() {
+					if collide(transform.translation, Vec2::splat(30.), wall_transform.translation, Vec2::splat(80.)).is_some() {
+						hits.send(HitEvent { target: wall_entity, damage: FRAGMENT_DAMAGE, knockback: Vec2::ZERO });
 					}
 				}
 				//info!("collided");
@@ -1360,4 +1537,66 @@ fn update_count(
 	//sprite.index = (player.b_count).round() as usize;
 	//Use health to determine the index of the health sprite to show
 	//if inventory.b_count != player_query. {
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn bundled_jump_settings_parse() {
+		let settings: JumpSettings = ron::from_str(include_str!("../assets/jump.ron")).unwrap();
+		assert_eq!(settings, JumpSettings::default());
+	}
+
+	#[test]
+	fn swings_reach_the_next_tile_over() {
+		let pos = Vec3::ZERO;
+		for (facing, aim, tile) in [
+			(1., SwingAim::Forward, Vec3::new(TILE_SIZE, 0., 0.)),
+			(-1., SwingAim::Forward, Vec3::new(-TILE_SIZE, 0., 0.)),
+			(-1., SwingAim::Up, Vec3::new(0., TILE_SIZE, 0.)),
+			(1., SwingAim::Down, Vec3::new(0., -TILE_SIZE, 0.)),
+		] {
+			let (center, size) = swing_hitbox(pos, facing, aim, TILE_SIZE);
+			assert!(collide(center, size, tile, Vec2::splat(TILE_SIZE)).is_some(), "{:?} {}", aim, facing);
+			//and never the tile behind
+			assert!(collide(center, size, -tile, Vec2::splat(TILE_SIZE)).is_none(), "{:?} {}", aim, facing);
+		}
+	}
+
+	#[test]
+	fn charged_throws_go_further() {
+		let tap = throw_velocity(1., SwingAim::Forward, 0.);
+		let full = throw_velocity(1., SwingAim::Forward, THROW_CHARGE_TIME * 2.);
+		assert!(tap.x > 0. && tap.y > 0.);
+		assert!(full.length() > tap.length());
+		assert!((full.length() - THROW_CHARGED_SPEED).abs() < 0.01);
+		//facing left mirrors it, and holding up lobs it higher than it goes across
+		assert_eq!(throw_velocity(-1., SwingAim::Forward, 0.).x, -tap.x);
+		let lob = throw_velocity(1., SwingAim::Up, 0.);
+		assert!(lob.y > lob.x);
+		//holding down just rolls it off the miner's feet, however long it was held
+		assert_eq!(throw_velocity(1., SwingAim::Down, THROW_CHARGE_TIME), Vec2::new(DROP_SPEED, 0.));
+	}
+
+	#[test]
+	fn bounces_lose_speed_until_the_bomb_settles() {
+		let mut velocity = -THROW_CHARGED_SPEED;
+		let mut bounces = 0;
+		while let Some(next) = bounce(velocity) {
+			assert!(next.abs() < velocity.abs() && next.signum() == -velocity.signum());
+			velocity = -next.abs();
+			bounces += 1;
+		}
+		assert!(bounces > 0 && bounces < 10);
+	}
+
+	#[test]
+	fn gravity_eases_off_at_the_apex() {
+		let settings = JumpSettings::default();
+		let full = settings.gravity * TILE_SIZE;
+		assert_eq!(settings.gravity_at(10. * TILE_SIZE), full);
+		assert_eq!(settings.gravity_at(0.), full * settings.apex_gravity);
+		assert_eq!(settings.gravity_at(-10. * TILE_SIZE), full);
+	}
+}
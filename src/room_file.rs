@@ -0,0 +1,301 @@
+use std::fmt;
+
+use bevy::{
+	asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+	prelude::*,
+	reflect::TypeUuid,
+};
+
+use crate::{
+	ROOM_WIDTH,
+	ROOM_HEIGHT,
+	level::{
+		Room,
+		LEFT,
+		RIGHT,
+		TOP,
+		BOTTOM,
+	},
+	connectivity::repair_room,
+};
+
+/*
+Room files (assets/rooms/<name>.room) hold one or more hand-authored rooms:
+
+	exits=LRTB tags=start,set_piece difficulty=0
+	----------------
+	---------#------
+	... ROOM_HEIGHT (9) rows, each exactly ROOM_WIDTH (16) tiles
+	!
+	exits=TB tags=roost difficulty=4
+	...
+
+Every room starts with a header line of space separated key=value fields:
+	exits       open sides, any of L R T B (required, a bare `exits=` is a sealed room)
+	tags        comma separated labels, `start` marks the start room and `lair` the boss lair (optional)
+	difficulty  lowest room danger the template can be placed at, see difficulty.rs (optional, defaults to 0)
+then its rows of tiles. A line holding only `!` ends a room, the last room in a file doesn't need one.
+Blank lines are only allowed between rooms.
+
+Tiles: - open, # brick, U unbreakable brick, D door, E bat, G groundhog, T turtle boss, B bombs, H heart, L lava, W water, A arrow trap, N nest,
+       P pickaxe upgrade, M anvil (sells the next pickaxe for bombs)
+       a-z are left for enemies defined in assets/enemies, see enemy_def.rs
+Border tiles on closed sides are turned into U when the room is loaded.
+*/
+
+pub const TILE_CHARS: &str = "-#UDETBHLWAGNPM";
+
+// A hand-authored room, used in place of a generated cave wherever its exits fit
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomTemplate {
+	pub room: Room,
+	pub tags: Vec<String>,
+	pub difficulty: u32,
+}
+
+impl RoomTemplate {
+	pub fn has_tag(&self, tag: &str) -> bool {
+		self.tags.iter().any(|t| t == tag)
+	}
+
+	// Fits if it matches every exit that's already decided by a neighbour, isn't too hard for
+	// where it's going and doesn't leave the player in a dead end
+	pub fn fits(&self, required: &[Option<bool>;4], danger: f32) -> bool {
+		let exits = self.room.exits;
+		let open_exits = exits.iter().filter(|e| **e).count();
+		let all_decided = required.iter().all(|r| r.is_some());
+		self.difficulty as f32 <= danger
+			&& required.iter().zip(exits).all(|(r, e)| r.is_none_or(|r| r == e))
+			&& (open_exits >= 2 || all_decided)
+	}
+}
+
+// Where in the file something went wrong, lines and columns count from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomParseError {
+	pub line: usize,
+	pub column: usize,
+	pub message: String,
+}
+
+impl RoomParseError {
+	fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+		Self { line, column, message: message.into() }
+	}
+}
+
+impl fmt::Display for RoomParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+	}
+}
+
+impl std::error::Error for RoomParseError {}
+
+// splits a header into its fields, keeping the column each one starts at
+fn header_fields(line: &str) -> Vec<(usize, &str)> {
+	let mut fields = Vec::new();
+	let mut start = None;
+	for (i, c) in line.char_indices() {
+		match (c.is_whitespace(), start) {
+			(false, None) => start = Some(i),
+			(true, Some(s)) => {
+				fields.push((s, &line[s..i]));
+				start = None;
+			}
+			_ => {}
+		}
+	}
+	if let Some(s) = start {
+		fields.push((s, &line[s..]));
+	}
+	fields.into_iter().map(|(i, field)| (line[..i].chars().count() + 1, field)).collect()
+}
+
+fn parse_header(line_no: usize, line: &str) -> Result<RoomTemplate, RoomParseError> {
+	let mut template = RoomTemplate { room: Room::new([false; 4]), tags: Vec::new(), difficulty: 0 };
+	let mut has_exits = false;
+	for (column, field) in header_fields(line) {
+		let (key, value) = field.split_once('=')
+			.ok_or_else(|| RoomParseError::new(line_no, column, format!("expected key=value, found {:?}", field)))?;
+		let value_column = column + key.chars().count() + 1;
+		match key {
+			"exits" => {
+				has_exits = true;
+				for (i, c) in value.chars().enumerate() {
+					let side = match c {
+						'L' => LEFT,
+						'R' => RIGHT,
+						'T' => TOP,
+						'B' => BOTTOM,
+						_ => return Err(RoomParseError::new(line_no, value_column + i, format!("unknown exit {:?}, expected one of L R T B", c))),
+					};
+					template.room.exits[side] = true;
+				}
+			}
+			"tags" => template.tags = value.split(',').filter(|t| !t.is_empty()).map(String::from).collect(),
+			"difficulty" => {
+				template.difficulty = value.parse()
+					.map_err(|_| RoomParseError::new(line_no, value_column, format!("difficulty should be a whole number, found {:?}", value)))?;
+			}
+			_ => return Err(RoomParseError::new(line_no, column, format!("unknown header field {:?}", key))),
+		}
+	}
+	if !has_exits {
+		return Err(RoomParseError::new(line_no, 1, "room header is missing exits="));
+	}
+	Ok(template)
+}
+
+fn parse_row(line_no: usize, line: &str, row: &mut [char; ROOM_WIDTH]) -> Result<(), RoomParseError> {
+	let mut width = 0;
+	for (i, c) in line.chars().enumerate() {
+		if !TILE_CHARS.contains(c) && !c.is_ascii_lowercase() {
+			return Err(RoomParseError::new(line_no, i + 1, format!("unknown tile {:?}", c)));
+		}
+		if i < ROOM_WIDTH {
+			row[i] = c;
+		}
+		width += 1;
+	}
+	if width != ROOM_WIDTH {
+		return Err(RoomParseError::new(line_no, width.min(ROOM_WIDTH) + 1, format!("row is {} tiles wide, expected {}", width, ROOM_WIDTH)));
+	}
+	Ok(())
+}
+
+// Walls off every closed side with unbreakable tiles so nobody can dig out of the room there
+fn seal_closed_sides(room: &mut Room) {
+	for (i, row) in room.room_coords.iter_mut().enumerate() {
+		for (j, character) in row.iter_mut().enumerate() {
+			if (i == 0 && !room.exits[TOP])|| (j == 0 && !room.exits[LEFT]) || (i == ROOM_HEIGHT - 1 && !room.exits[BOTTOM]) || (j == ROOM_WIDTH - 1 && !room.exits[RIGHT]){
+				*character = 'U';
+			}
+		}
+	}
+}
+
+fn finish_room(mut template: RoomTemplate, templates: &mut Vec<RoomTemplate>) {
+	seal_closed_sides(&mut template.room);
+	let carved = repair_room(&mut template.room);
+	if carved > 0 {
+		warn!("room template {:?} had exits that don't connect, carved {} tiles", template.tags, carved);
+	}
+	templates.push(template);
+}
+
+pub fn parse_rooms(text: &str) -> Result<Vec<RoomTemplate>, RoomParseError> {
+	let mut templates = Vec::new();
+	let mut current: Option<(RoomTemplate, usize)> = None; //room being read and how many rows it has so far
+	let mut line_count = 0;
+
+	for (i, line) in text.lines().enumerate() {
+		let line_no = i + 1;
+		let line = line.trim_end_matches('\r');
+		line_count = line_no;
+		current = match current.take() {
+			None if line.trim().is_empty() => None,
+			None if line == "!" => return Err(RoomParseError::new(line_no, 1, "room separator without a room before it")),
+			None => Some((parse_header(line_no, line)?, 0)),
+			Some((_, rows)) if line == "!" && rows < ROOM_HEIGHT => {
+				return Err(RoomParseError::new(line_no, 1, format!("room has {} rows, expected {}", rows, ROOM_HEIGHT)));
+			}
+			Some((template, _)) if line == "!" => {
+				finish_room(template, &mut templates);
+				None
+			}
+			Some((_, rows)) if rows == ROOM_HEIGHT => {
+				return Err(RoomParseError::new(line_no, 1, format!("room has more than {} rows, missing a `!`?", ROOM_HEIGHT)));
+			}
+			Some((mut template, rows)) => {
+				parse_row(line_no, line, &mut template.room.room_coords[rows])?;
+				Some((template, rows + 1))
+			}
+		};
+	}
+
+	match current {
+		Some((_, rows)) if rows < ROOM_HEIGHT => {
+			Err(RoomParseError::new(line_count + 1, 1, format!("file ends after {} rows of a room, expected {}", rows, ROOM_HEIGHT)))
+		}
+		Some((template, _)) => {
+			finish_room(template, &mut templates);
+			Ok(templates)
+		}
+		None if templates.is_empty() => Err(RoomParseError::new(1, 1, "no rooms in file")),
+		None => Ok(templates),
+	}
+}
+
+#[derive(TypeUuid)]
+#[uuid = "3f6f1c2e-8b7a-4d52-9c1e-5a7d2b9e4f10"]
+pub struct RoomFile(pub Vec<RoomTemplate>);
+
+#[derive(Default)]
+pub struct RoomFileLoader;
+
+impl AssetLoader for RoomFileLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let text = std::str::from_utf8(bytes)?;
+			let templates = parse_rooms(text)
+				.map_err(|e| anyhow::anyhow!("{}: {}", load_context.path().display(), e))?;
+			load_context.set_default_asset(LoadedAsset::new(RoomFile(templates)));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["room"]
+	}
+}
+
+// The room files shipped in assets/, for tests that need a real template pool
+#[cfg(test)]
+pub fn bundled_templates() -> Vec<RoomTemplate> {
+	let mut templates = parse_rooms(include_str!("../assets/rooms/start.room")).unwrap();
+	templates.extend(parse_rooms(include_str!("../assets/rooms/set_pieces.room")).unwrap());
+	templates.extend(parse_rooms(include_str!("../assets/rooms/lair.room")).unwrap());
+	templates
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn room(header: &str, rows: &[&str]) -> String {
+		format!("{}\n{}\n", header, rows.join("\n"))
+	}
+
+	const OPEN_ROW: &str = "----------------";
+
+	#[test]
+	fn bundled_room_files_parse() {
+		assert!(parse_rooms(include_str!("../assets/rooms/start.room")).unwrap()[0].has_tag("start"));
+		assert!(parse_rooms(include_str!("../assets/rooms/set_pieces.room")).unwrap().len() > 1);
+	}
+
+	#[test]
+	fn errors_point_at_line_and_column() {
+		let mut rows = [OPEN_ROW; ROOM_HEIGHT];
+		rows[3] = "-------X--------";
+		let err = parse_rooms(&room("exits=LR", &rows)).unwrap_err();
+		assert_eq!((err.line, err.column), (5, 8));
+
+		let err = parse_rooms(&room("exits=LQ", &[OPEN_ROW; ROOM_HEIGHT])).unwrap_err();
+		assert_eq!((err.line, err.column), (1, 8));
+	}
+
+	#[test]
+	fn wrong_dimensions_are_rejected() {
+		let mut rows = [OPEN_ROW; ROOM_HEIGHT];
+		rows[0] = "---------------";
+		assert_eq!(parse_rooms(&room("exits=LR", &rows)).unwrap_err().line, 2);
+		assert!(parse_rooms(&room("exits=LR", &[OPEN_ROW; ROOM_HEIGHT - 1])).is_err());
+		assert!(parse_rooms(&room("exits=LR", &[OPEN_ROW; ROOM_HEIGHT + 1])).is_err());
+	}
+}
@@ -6,6 +6,8 @@ use iyes_loopless::prelude::*;
 
 use crate::{
 	GameState,
+	level::MapSeed,
+	controls::Rebinding,
 };
 
 pub struct MainMenuPlugin;
@@ -13,12 +15,14 @@ impl Plugin for MainMenuPlugin{
     fn build(&self, app: &mut App){
         app.add_enter_system(GameState::MainMenu, setup_menu)
         .add_system(handle_start_button.run_in_state(GameState::MainMenu))
+        .add_system(edit_seed.run_in_state(GameState::MainMenu))
 		//.add_system_set(SystemSet::on_pause(GameState::MainMenu).with_system(despawn_menu));
         .add_enter_system(GameState::Playing, despawn_menu);
     }
 }
 
-fn setup_menu(mut commands: Commands, assets: Res<AssetServer>){
+fn setup_menu(mut commands: Commands, assets: Res<AssetServer>, seed: Res<MapSeed>){
+	commands.insert_resource(SeedEntry::default());
     let ui_assets = UiAssets{
 		font: assets.load("quattrocentosans-bold.ttf"),
 		button: assets.load("button.png"),
@@ -67,9 +71,49 @@ fn setup_menu(mut commands: Commands, assets: Res<AssetServer>){
 			});
 			
 	}); 
+	//seed readout, type digits to change it before starting
+	commands.spawn_bundle(TextBundle{
+		style: Style{
+			position_type: PositionType::Absolute,
+			position: Rect{
+				bottom: Val::Px(40.0),
+				left: Val::Px(40.0),
+				..Default::default()
+			},
+			..Default::default()
+		},
+		text: Text::with_section(
+			seed_label(&seed, false),
+			TextStyle{
+				font: ui_assets.font.clone(),
+				font_size: 30.0,
+				color: Color::rgb(0.9, 0.9, 0.9),
+			},
+			Default::default(),
+		),
+		..Default::default()
+	})
+	.insert(SeedText);
 	commands.insert_resource(ui_assets);
 }
 
+#[derive(Component)]
+struct SeedText;
+
+// The seed shown on the menu is a random one, typing starts a new number rather than adding to it
+#[derive(Default)]
+struct SeedEntry {
+	typing: bool,
+}
+
+fn seed_label(seed: &MapSeed, rejected: bool) -> String {
+	if rejected {
+		format!("Seed: {}  (too long, that digit was ignored)", seed.0)
+	} else {
+		format!("Seed: {}  (type digits to change, F1 to rebind controls)", seed.0)
+	}
+}
+
 struct UiAssets{
 	font: Handle<Font>,
 	button: Handle<Image>,
@@ -99,7 +143,49 @@ fn handle_start_button(
 	}
 }
 
-fn despawn_menu(mut commands: Commands, button_query: Query<Entity, With<Button>>) 
+fn edit_seed(
+	mut chars: EventReader<ReceivedCharacter>,
+	input: Res<Input<KeyCode>>,
+	mut seed: ResMut<MapSeed>,
+	mut entry: ResMut<SeedEntry>,
+	rebinding: Option<Res<Rebinding>>,
+	mut text_query: Query<&mut Text, With<SeedText>>,
+){
+	//keys pressed while rebinding belong to the rebinding, so skip past them
+	if rebinding.is_some() {
+		chars.iter().for_each(drop);
+		return;
+	}
+	let typed: Vec<char> = chars.iter().map(|ev| ev.char).filter(|c| c.is_ascii_digit()).collect();
+	let backspace = input.just_pressed(KeyCode::Back);
+	if typed.is_empty() && !backspace {
+		return;
+	}
+	//a digit on the untouched seed starts a new one, backspace edits the one that's there
+	let mut digits = if entry.typing || typed.is_empty() { seed.0.to_string() } else { String::new() };
+	entry.typing = true;
+	if backspace {
+		digits.pop();
+	}
+	let mut rejected = false;
+	for c in typed {
+		if digits == "0" {
+			digits.clear();
+		}
+		digits.push(c);
+		//anything that no longer fits in a u64 is dropped
+		if digits.parse::<u64>().is_err() {
+			digits.pop();
+			rejected = true;
+		}
+	}
+	*seed = MapSeed(digits.parse().unwrap_or(0));
+	for mut text in text_query.iter_mut(){
+		text.sections[0].value = seed_label(&seed, rejected);
+	}
+}
+
+fn despawn_menu(mut commands: Commands, button_query: Query<Entity, Or<(With<Button>, With<SeedText>)>>) 
 {
     for ent in button_query.iter(){
 		commands.entity(ent).despawn_recursive();
@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use bevy::sprite::collide_aabb::collide;
+
+use crate::{
+	WIN_W,
+	WIN_H,
+	TILE_SIZE,
+	GameState,
+	loading::{
+		LoadingAssets,
+		LoadingAssetInfo,
+	},
+	level::{
+		Brick,
+		Collider,
+		RoomTile,
+	},
+	enemy::Enemy,
+	player::{
+		Player,
+		Health,
+	},
+	combat::{
+		HitEvent,
+		hit_stop_over,
+	},
+};
+
+const TRAP_HEALTH: f32 = 40.;
+const TRAP_COOLDOWN: f32 = 1.5; //seconds between shots
+const ARROW_SPEED: f32 = 10. * TILE_SIZE; //per second
+const ARROW_DAMAGE: f32 = 15.;
+const ARROW_KNOCKBACK: f32 = 5. * TILE_SIZE;
+const ARROW_SIZE: f32 = 20.;
+
+// Wall tile that shoots along its row or column whenever it can see the player.
+// It's also a Brick, so the pickaxe and bomb fragments break it like any other brick.
+#[derive(Component)]
+pub struct ArrowTrap {
+	cooldown: Timer,
+}
+
+#[derive(Component)]
+pub struct Arrow {
+	velocity: Vec2,
+}
+
+pub struct TrapImage(Handle<Image>);
+pub struct ArrowImage(Handle<Image>);
+
+pub struct TrapPlugin;
+impl Plugin for TrapPlugin {
+	fn build (&self, app: &mut App) {
+		app.add_enter_system(GameState::Loading, load_trap_images)
+			.add_system(fire_traps.run_in_state(GameState::Playing))
+			.add_system(move_arrows.run_in_state(GameState::Playing).run_if(hit_stop_over));
+	}
+}
+
+fn load_trap_images(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut loading_assets: ResMut<LoadingAssets>,
+) {
+	let trap_handle = asset_server.load("arrow_trap.png");
+	loading_assets.insert(
+		trap_handle.clone_untyped(),
+		LoadingAssetInfo::for_handle(trap_handle.clone_untyped(), &asset_server),
+	);
+	commands.insert_resource(TrapImage(trap_handle));
+
+	let arrow_handle = asset_server.load("bolt.png");
+	loading_assets.insert(
+		arrow_handle.clone_untyped(),
+		LoadingAssetInfo::for_handle(arrow_handle.clone_untyped(), &asset_server),
+	);
+	commands.insert_resource(ArrowImage(arrow_handle));
+}
+
+pub fn spawn_trap(commands: &mut Commands, trap_image: &TrapImage, translation: Vec3, tile: RoomTile, health_scale: f32) -> Entity {
+	commands
+		.spawn_bundle(SpriteBundle {
+			texture: trap_image.0.clone(),
+			sprite: Sprite {
+				custom_size: Some(Vec2::splat(TILE_SIZE)),
+				..default()
+			},
+			transform: Transform::from_translation(translation),
+			..default()
+		})
+		.insert(ArrowTrap { cooldown: Timer::from_seconds(TRAP_COOLDOWN, false) })
+		.insert(Health::from(TRAP_HEALTH * health_scale))
+		.insert(Brick)
+		.insert(Collider)
+		.insert(tile)
+		.id()
+}
+
+// Direction from `from` to `to` if they share a row or column and no collider sits between them
+pub fn line_of_sight(from: Vec3, to: Vec3, colliders: impl Iterator<Item = Vec3>) -> Option<Vec2> {
+	let delta = (to - from).truncate();
+	let dir = if delta.y.abs() < TILE_SIZE / 2. {
+		Vec2::new(delta.x.signum(), 0.)
+	} else if delta.x.abs() < TILE_SIZE / 2. {
+		Vec2::new(0., delta.y.signum())
+	} else {
+		return None;
+	};
+	let distance = delta.dot(dir);
+	let blocked = colliders.into_iter().any(|wall| {
+		let offset = (wall - from).truncate();
+		let along = offset.dot(dir);
+		let across = (offset - dir * along).length();
+		along > TILE_SIZE / 2. && along < distance && across < TILE_SIZE / 2.
+	});
+	if blocked { None } else { Some(dir) }
+}
+
+fn fire_traps(
+	mut commands: Commands,
+	time: Res<Time>,
+	arrow_image: Res<ArrowImage>,
+	mut trap_query: Query<(&Transform, &mut ArrowTrap)>,
+	collider_query: Query<&Transform, With<Collider>>,
+	player_query: Query<&Transform, With<Player>>,
+) {
+	let player_transform = match player_query.get_single() {
+		Ok(transform) => transform,
+		Err(_) => return,
+	};
+	for (trap_transform, mut trap) in trap_query.iter_mut() {
+		trap.cooldown.tick(time.delta());
+		if !trap.cooldown.finished() {
+			continue;
+		}
+		let walls = collider_query.iter().map(|wall| wall.translation);
+		if let Some(dir) = line_of_sight(trap_transform.translation, player_transform.translation, walls) {
+			trap.cooldown.reset();
+			commands
+				.spawn_bundle(SpriteBundle {
+					texture: arrow_image.0.clone(),
+					sprite: Sprite {
+						custom_size: Some(Vec2::splat(ARROW_SIZE * 1.5)),
+						..default()
+					},
+					transform: Transform {
+						translation: trap_transform.translation + (dir * TILE_SIZE * 0.6).extend(800.),
+						rotation: Quat::from_rotation_z(dir.y.atan2(dir.x)),
+						..default()
+					},
+					..default()
+				})
+				.insert(Arrow { velocity: dir * ARROW_SPEED });
+		}
+	}
+}
+
+// Arrows fly straight until they hit something, hurting whatever that was
+fn move_arrows(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut hit_events: EventWriter<HitEvent>,
+	mut arrow_query: Query<(Entity, &Arrow, &mut Transform)>,
+	target_query: Query<(Entity, &Transform), (Or<(With<Player>, With<Enemy>)>, Without<Arrow>)>,
+	brick_query: Query<(Entity, &Transform), (With<Brick>, With<Health>, Without<Player>, Without<Arrow>)>,
+	wall_query: Query<&Transform, (With<Collider>, Without<Health>, Without<Arrow>)>,
+) {
+	for (arrow_entity, arrow, mut transform) in arrow_query.iter_mut() {
+		transform.translation += (arrow.velocity * time.delta_seconds()).extend(0.);
+		let pos = transform.translation;
+		let hits = |other: Vec3, size: f32| collide(pos, Vec2::splat(ARROW_SIZE), other, Vec2::splat(size)).is_some();
+
+		if pos.x.abs() > WIN_W / 2. || pos.y.abs() > WIN_H / 2. {
+			commands.entity(arrow_entity).despawn();
+			continue;
+		}
+
+		if let Some((entity, target)) = target_query.iter().find(|(_, target)| hits(target.translation, 50.)) {
+			commands.entity(arrow_entity).despawn();
+			let from = pos - arrow.velocity.extend(0.);
+			hit_events.send(HitEvent::from_point(entity, ARROW_DAMAGE, ARROW_KNOCKBACK, from, target.translation));
+			continue;
+		}
+
+		if let Some((entity, _)) = brick_query.iter().find(|(_, target)| hits(target.translation, 50.)) {
+			commands.entity(arrow_entity).despawn();
+			hit_events.send(HitEvent { target: entity, damage: ARROW_DAMAGE, knockback: Vec2::ZERO });
+			continue;
+		}
+
+		if wall_query.iter().any(|wall| hits(wall.translation, TILE_SIZE)) {
+			commands.entity(arrow_entity).despawn();
+		}
+	}
+}
@@ -0,0 +1,264 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+	TILE_SIZE,
+	ROOM_WIDTH,
+	ROOM_HEIGHT,
+	GameState,
+	loading::{
+		LoadingAssets,
+		LoadingAssetInfo,
+	},
+	level::{
+		Map,
+		Room,
+		ActiveRoom,
+		tile_at,
+		tile_center,
+	},
+	player::Player,
+	combat::HitEvent,
+};
+
+/*
+Water lives in the room grid as 'W' tiles and moves one tile per flow step, like falling sand:
+a water tile falls if it can, slides diagonally down if it can, and only spreads sideways when
+there's water above it pushing it along. Anything that turns a tile back into '-' (mining,
+bombs) opens it up for the water around it.
+*/
+
+const FLOW_TIME: f32 = 0.15; //seconds between flow steps
+const BREATH_TIME: f32 = 5.; //seconds the player can stay under before drowning
+const DROWN_TIME: f32 = 1.; //seconds between hits once out of breath
+const DROWN_DAMAGE: f32 = 10.;
+pub const SWIM_SPEED: f32 = 0.5; //multiplier on walking speed and gravity while swimming
+
+#[derive(Component)]
+pub struct Water;
+
+// How long the player has been under, kept on the player
+#[derive(Component)]
+pub struct Breath {
+	pub swimming: bool,
+	breath: Timer,
+	drown: Timer,
+}
+
+impl Breath {
+	pub fn new() -> Self {
+		Self {
+			swimming: false,
+			breath: Timer::from_seconds(BREATH_TIME, false),
+			drown: Timer::from_seconds(DROWN_TIME, true),
+		}
+	}
+}
+
+pub struct WaterImage(Handle<Image>);
+
+struct Flow {
+	timer: Timer,
+	to_right: bool, //which way water tries first, flipped every step so it doesn't all drift one way
+}
+
+pub struct WaterPlugin;
+impl Plugin for WaterPlugin {
+	fn build (&self, app: &mut App) {
+		app.insert_resource(Flow { timer: Timer::from_seconds(FLOW_TIME, true), to_right: false })
+			.add_enter_system(GameState::Loading, load_water)
+			.add_system(flow_water.run_in_state(GameState::Playing))
+			.add_system(breathe.run_in_state(GameState::Playing));
+	}
+}
+
+fn load_water(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut loading_assets: ResMut<LoadingAssets>,
+) {
+	let water_handle = asset_server.load("water_block.png");
+	loading_assets.0.insert(
+		water_handle.clone_untyped(),
+		LoadingAssetInfo::for_handle(water_handle.clone_untyped(), &asset_server),
+	);
+	commands.insert_resource(WaterImage(water_handle));
+}
+
+pub fn spawn_water_tile(commands: &mut Commands, water_image: &WaterImage, x: usize, y: usize) {
+	commands
+		.spawn_bundle(SpriteBundle {
+			texture: water_image.0.clone(),
+			sprite: Sprite {
+				custom_size: Some(Vec2::splat(TILE_SIZE)),
+				color: Color::rgba(1., 1., 1., 0.7),
+				..default()
+			},
+			transform: Transform::from_translation(tile_center(x, y).extend(150.)),
+			..default()
+		})
+		.insert(Water);
+}
+
+// Moves every water tile at most once, returns whether anything moved
+pub fn flow_step(room: &mut Room, to_right: bool) -> bool {
+	let grid = &mut room.room_coords;
+	let mut moved = [[false; ROOM_WIDTH]; ROOM_HEIGHT];
+	let mut changed = false;
+	let sides: [isize; 2] = if to_right { [1, -1] } else { [-1, 1] };
+
+	//bottom up so a falling column moves together
+	for i in (0..ROOM_HEIGHT).rev() {
+		for j in 0..ROOM_WIDTH {
+			if grid[i][j] != 'W' || moved[i][j] {
+				continue;
+			}
+			let below = i + 1 < ROOM_HEIGHT;
+			//water stacked above or leaning on it from a higher tile pushes it sideways
+			let pressed = i > 0 && (grid[i-1][j] == 'W'
+				|| (j > 0 && grid[i-1][j-1] == 'W')
+				|| (j + 1 < ROOM_WIDTH && grid[i-1][j+1] == 'W'));
+			let mut target = None;
+			if below && grid[i+1][j] == '-' {
+				target = Some((i + 1, j));
+			} else {
+				for side in sides {
+					let sj = j as isize + side;
+					if sj < 0 || sj >= ROOM_WIDTH as isize || grid[i][sj as usize] != '-' {
+						continue;
+					}
+					let sj = sj as usize;
+					if below && grid[i+1][sj] == '-' {
+						target = Some((i + 1, sj));
+						break;
+					}
+					if pressed {
+						target = Some((i, sj));
+						break;
+					}
+				}
+			}
+			if let Some((ti, tj)) = target {
+				grid[i][j] = '-';
+				grid[ti][tj] = 'W';
+				moved[ti][tj] = true;
+				changed = true;
+			}
+		}
+	}
+	changed
+}
+
+fn flow_water(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut flow: ResMut<Flow>,
+	active_room: Option<Res<ActiveRoom>>,
+	water_image: Res<WaterImage>,
+	mut map_query: Query<&mut Map>,
+	water_query: Query<Entity, With<Water>>,
+) {
+	if !flow.timer.tick(time.delta()).just_finished() {
+		return;
+	}
+	let active_room = match active_room {
+		Some(room) => room,
+		None => return,
+	};
+	let mut map = match map_query.get_single_mut() {
+		Ok(map) => map,
+		Err(_) => return,
+	};
+	let room = match map.room_mut(active_room.0, active_room.1) {
+		Some(room) => room,
+		None => return,
+	};
+	flow.to_right = !flow.to_right;
+	if !flow_step(room, flow.to_right) {
+		return;
+	}
+
+	for entity in water_query.iter() {
+		commands.entity(entity).despawn();
+	}
+	for (y, line) in room.room_coords.iter().enumerate() {
+		for (x, char) in line.iter().enumerate() {
+			if *char == 'W' {
+				spawn_water_tile(&mut commands, &water_image, x, y);
+			}
+		}
+	}
+}
+
+fn breathe(
+	time: Res<Time>,
+	mut hits: EventWriter<HitEvent>,
+	active_room: Option<Res<ActiveRoom>>,
+	map_query: Query<&Map>,
+	mut player_query: Query<(Entity, &Transform, &mut Breath), With<Player>>,
+) {
+	let (active_room, map) = match (active_room, map_query.get_single()) {
+		(Some(room), Ok(map)) => (room, map),
+		_ => return,
+	};
+	let room = match map.room(active_room.0, active_room.1) {
+		Some(room) => room,
+		None => return,
+	};
+	for (player_entity, transform, mut breath) in player_query.iter_mut() {
+		breath.swimming = tile_at(transform.translation).is_some_and(|(x, y)| room.room_coords[y][x] == 'W');
+		if !breath.swimming {
+			breath.breath.reset();
+			breath.drown.reset();
+			continue;
+		}
+		if !breath.breath.tick(time.delta()).finished() {
+			continue;
+		}
+		if breath.drown.tick(time.delta()).just_finished() {
+			hits.send(HitEvent { target: player_entity, damage: DROWN_DAMAGE, knockback: Vec2::ZERO });
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn room_from(rows: [&str; ROOM_HEIGHT]) -> Room {
+		let mut room = Room::new([false; 4]);
+		for (i, row) in rows.iter().enumerate() {
+			for (j, c) in row.chars().enumerate() {
+				room.room_coords[i][j] = c;
+			}
+		}
+		room
+	}
+
+	fn water_count(room: &Room) -> usize {
+		room.room_coords.iter().flatten().filter(|c| **c == 'W').count()
+	}
+
+	#[test]
+	fn water_fills_a_mined_pocket_and_settles() {
+		let mut room = room_from([
+			"################",
+			"#WWW-----------#",
+			"#WWW-----------#",
+			"#####-##########",
+			"#####-##########",
+			"################",
+			"################",
+			"################",
+			"################",
+		]);
+		let mut steps = 0;
+		while flow_step(&mut room, steps % 2 == 0) {
+			steps += 1;
+			assert!(steps < 200, "water never settled");
+		}
+		assert_eq!(water_count(&room), 6);
+		assert_eq!(room.room_coords[4][5], 'W');
+		assert_eq!(room.room_coords[3][5], 'W');
+	}
+}